@@ -0,0 +1,144 @@
+"""
+Text normalization applied before G2P. Every substitution is recorded so a
+request can report exactly how its input was rewritten.
+"""
+import re
+from typing import Callable, Dict, List, Optional, Tuple
+from num2words import CONVERTER_CLASSES, num2words
+from pydantic import BaseModel, ConfigDict, Field
+
+class Normalization(BaseModel):
+    model_config = ConfigDict(populate_by_name=True)
+
+    source: str = Field(alias="from")
+    target: str = Field(alias="to")
+    kind: str
+
+# Titles always precede a name, so their period never ends a sentence
+EN_TITLES = {
+    "Dr": "Doctor", "Mr": "Mister", "Mrs": "Missus", "Ms": "Miz",
+    "Prof": "Professor", "Mt": "Mount", "Gen": "General", "Sgt": "Sergeant",
+}
+# These may end a sentence, in which case the period is kept
+EN_ABBREVIATIONS = {
+    "Jr": "Junior", "Sr": "Senior", "vs": "versus", "etc": "et cetera",
+    "approx": "approximately", "dept": "department",
+}
+
+# Currency symbols read out in number_mode "cardinal", by ISO code
+CURRENCY_CODES = {"$": "USD", "€": "EUR", "£": "GBP"}
+
+# Digit sequences with separators, e.g. "555-1234", "(555) 123 4567", "+1 555 0100"
+PHONE_NUMBER = re.compile(r"(?<![\w.])\+?\(?\d[\d().\s-]{5,}\d(?!\w)")
+
+class Normalizer:
+    """Applies regex substitutions to a text while logging each change."""
+
+    def __init__(self, text: str):
+        self.text = text
+        self.applied: List[Normalization] = []
+
+    def sub(self, pattern: str, kind: str, replace: Callable[[re.Match], str], flags: int = 0):
+        def record(match: re.Match) -> str:
+            out = replace(match)
+            if out != match.group(0):
+                self.applied.append(Normalization(source=match.group(0), target=out, kind=kind))
+            return out
+        self.text = re.sub(pattern, record, self.text, flags=flags)
+
+def ends_sentence(m: re.Match) -> bool:
+    """Whether the period of an abbreviation also ends its sentence."""
+    return m.end() == len(m.string) or bool(re.match(r"\s+[A-Z]", m.string[m.end():]))
+
+def normalize_abbreviations(n: Normalizer):
+    n.sub(r"\b(%s)\." % "|".join(EN_TITLES), "abbreviation", lambda m: EN_TITLES[m.group(1)])
+    # "St." is "Saint" before a name and "Street" otherwise
+    n.sub(r"\bSt\.(?=\s+[A-Z])", "abbreviation", lambda m: "Saint")
+    n.sub(r"\bSt\.", "abbreviation", lambda m: "Street." if ends_sentence(m) else "Street")
+    n.sub(r"\b(%s)\." % "|".join(EN_ABBREVIATIONS), "abbreviation",
+          lambda m: EN_ABBREVIATIONS[m.group(1)] + ("." if ends_sentence(m) else ""))
+
+def normalize_repeated_punctuation(n: Normalizer):
+    """
+    Collapse runs like "!!!" or "??" into one mark. A question mark anywhere in
+    the run keeps question intonation; exclamations carry their emphasis as
+    raised stress on the preceding word (misaki [word](+1) markup).
+    """
+    def collapse(m: re.Match) -> str:
+        word, marks = m.group("word") or "", m.group("marks")
+        if "!" in marks and word:
+            word = f"[{word}](+1)"
+        return word + ("?" if "?" in marks else "!")
+    n.sub(r"(?P<word>[^\W\d_]+(?:'[^\W\d_]+)?)?(?P<marks>[!?]{2,})", "punctuation", collapse)
+
+def split_camel_case(n: Normalizer):
+    """Read identifiers word by word: "getUserName" -> "get User Name", "HTTPServer" -> "HTTP Server"."""
+    n.sub(r"\b[^\W\d_]+\b", "camel_case",
+          lambda m: re.sub(r"(?<=[a-z])(?=[A-Z])|(?<=[A-Z])(?=[A-Z][a-z])", " ", m.group(0)))
+
+def spell_digits(text: str) -> str:
+    """"555-1234" -> "5 5 5, 1 2 3 4": one digit at a time, a pause between groups."""
+    return ", ".join(" ".join(group) for group in re.split(r"\D+", text) if group)
+
+def is_phone_number(text: str) -> bool:
+    digits = sum(c.isdigit() for c in text)
+    return digits >= 7 and (text.startswith("+") or bool(re.search(r"[().\s-]", text)))
+
+def currency_words(symbol: str, amount: str, cents: Optional[str], lang: str) -> Optional[str]:
+    """"$5.50" -> "five dollars fifty cents" in `lang`; None if num2words has no names for the currency."""
+    converter = CONVERTER_CLASSES.get(lang)
+    forms = getattr(converter, "CURRENCY_FORMS", {}).get(CURRENCY_CODES[symbol])
+    if not forms:
+        return None
+    whole = int(amount.replace(",", ""))
+    words = f"{num2words(whole, lang=lang)} {converter.pluralize(whole, forms[0])}"
+    if cents and int(cents):
+        words += f" {num2words(int(cents), lang=lang)} {converter.pluralize(int(cents), forms[1])}"
+    return words
+
+def normalize_numbers(n: Normalizer, mode: str, lang: Optional[str]):
+    """
+    Render numbers per `mode`: "digits" reads every digit individually,
+    "cardinal" spells numbers out as quantities (needs a num2words language),
+    and "auto" reads phone numbers and zero-padded codes digit by digit while
+    leaving other numbers to the G2P (which also handles years). "cardinal"
+    first reads currency amounts and English ordinals ("21st").
+    """
+    if mode == "digits":
+        n.sub(r"\d+", "number", lambda m: " ".join(m.group(0)))
+    elif mode == "auto":
+        n.sub(PHONE_NUMBER, "number",
+              lambda m: spell_digits(m.group(0)) if is_phone_number(m.group(0)) else m.group(0))
+        n.sub(r"\b0\d+\b", "number", lambda m: " ".join(m.group(0)))
+    elif mode == "cardinal" and lang:
+        n.sub(r"([%s])(\d{1,3}(?:,\d{3})+|\d+)(?:\.(\d{2}))?\b" % "".join(CURRENCY_CODES), "currency",
+              lambda m: currency_words(m.group(1), m.group(2), m.group(3), lang) or m.group(0))
+        n.sub(r"\b(\d+)(?:st|nd|rd|th)\b", "ordinal", lambda m: num2words(int(m.group(1)), lang=lang, to="ordinal"))
+
+        def cardinal(m: re.Match) -> str:
+            value = m.group(0).replace(",", "")
+            return num2words(float(value) if "." in value else int(value), lang=lang)
+        n.sub(r"\d{1,3}(?:,\d{3})+(?:\.\d+)?|\d+(?:\.\d+)?", "number", cardinal)
+
+# Rule sets referenced by the "normalizer" key of a language table entry
+RULESETS: Dict[str, List[Callable[[Normalizer], None]]] = {
+    "en": [normalize_abbreviations, normalize_repeated_punctuation],
+}
+
+def normalize_text(text: str, ruleset: str | None,
+                   split_camel_case_words: bool = False,
+                   number_mode: Optional[str] = None,
+                   number_lang: Optional[str] = None) -> Tuple[str, List[Normalization]]:
+    """
+    Run the stages of `ruleset` (if any) over `text`, preceded by the
+    language-independent optional stages that are switched on.
+    `number_lang` is the num2words language used by number_mode "cardinal".
+    """
+    n = Normalizer(text)
+    if split_camel_case_words:
+        split_camel_case(n)
+    if number_mode:
+        normalize_numbers(n, number_mode, number_lang)
+    for stage in RULESETS.get(ruleset, []) if ruleset else []:
+        stage(n)
+    return n.text, n.applied
@@ -1,19 +1,280 @@
-from fastapi import FastAPI, Request, HTTPException
-from fastapi.responses import StreamingResponse
+from fastapi import BackgroundTasks, FastAPI, HTTPException, Query, Request, WebSocket, WebSocketDisconnect
+from fastapi.encoders import jsonable_encoder
+from fastapi.exceptions import RequestValidationError
+from fastapi.responses import JSONResponse, Response, StreamingResponse
+from starlette.exceptions import HTTPException as StarletteHTTPException
+from starlette.concurrency import iterate_in_threadpool, run_in_threadpool
+from collections import OrderedDict
+from contextlib import contextmanager
+from contextvars import ContextVar
+from dataclasses import dataclass, field, replace
+import asyncio
+import base64
+import gzip
+import hashlib
 import io
-import wave
+import json
+import math
+import logging
+import os
+import re
+import struct
+import threading
+import time
+import uuid
+import zlib
+import zipfile
+import numpy as np
+import random
 import sounddevice as sd
 import soundfile as sf
+import torch
 from kokoro import KPipeline, KModel
-from pydantic import BaseModel
-from typing import List
+from normalize import Normalization, normalize_text
+from ssml import SsmlError, parse_ssml
+from pydantic import BaseModel, Field, ValidationError
+from typing import Callable, Dict, Iterator, List, Literal, Optional
 from pathlib import Path
 
 app = FastAPI(title="Kokoro TTS API")
 
+# Logging: every record carries the correlation ID of the request it belongs to
+request_id_var: ContextVar[str] = ContextVar("request_id", default="-")
+
+class RequestIdFilter(logging.Filter):
+    def filter(self, record: logging.LogRecord) -> bool:
+        record.request_id = request_id_var.get()
+        return True
+
+# Attributes every LogRecord has; anything else was passed as `extra`
+LOG_RECORD_FIELDS = set(vars(logging.makeLogRecord({}))) | {"message", "asctime", "request_id"}
+
+class JsonFormatter(logging.Formatter):
+    """One JSON object per line: time, level, request ID, message and any `extra` fields."""
+
+    def format(self, record: logging.LogRecord) -> str:
+        entry = {
+            "time": self.formatTime(record),
+            "level": record.levelname,
+            "request_id": record.request_id,
+            "message": record.getMessage(),
+        }
+        entry.update({k: v for k, v in vars(record).items() if k not in LOG_RECORD_FIELDS})
+        if record.exc_info:
+            entry["exception"] = self.formatException(record.exc_info)
+        return json.dumps(entry, default=str)
+
+LOG_FORMATS = {
+    "text": logging.Formatter("%(asctime)s %(levelname)s [%(request_id)s] %(message)s"),
+    "json": JsonFormatter(),
+}
+
+def configure_logging(level: str, log_format: str):
+    log_handler.setFormatter(LOG_FORMATS[log_format])
+    logger.setLevel(level.upper())
+
+# Level and format from KOKORO_LOG_LEVEL / KOKORO_LOG_FORMAT, or --log-level / --log-format
+log_handler = logging.StreamHandler()
+log_handler.addFilter(RequestIdFilter())
+logger = logging.getLogger("kokorofile")
+logger.addHandler(log_handler)
+configure_logging(os.environ.get("KOKORO_LOG_LEVEL", "info"), os.environ.get("KOKORO_LOG_FORMAT", "text"))
+
+class APIError(HTTPException):
+    """
+    An HTTP error with a stable, machine-readable `error_code` (e.g.
+    EMPTY_TEXT, INVALID_VOICE, SYNTHESIS_FAILED) sent alongside `detail`.
+    """
+
+    def __init__(self, status_code: int, error_code: str, detail: str, headers: Optional[Dict[str, str]] = None):
+        super().__init__(status_code=status_code, detail=detail, headers=headers)
+        self.error_code = error_code
+
+# error_code of HTTP errors raised other than as APIError, e.g. by routing
+HTTP_ERROR_CODES = {404: "NOT_FOUND", 405: "METHOD_NOT_ALLOWED"}
+
+def http_error_code(error: StarletteHTTPException) -> str:
+    return getattr(error, "error_code", None) or HTTP_ERROR_CODES.get(error.status_code, f"HTTP_{error.status_code}")
+
 # Paths to models
 CORE_MODEL_PATH = Path("models/kokoro-v1_0.pth")
+# Model config (vocab and architecture); fetched from MODEL_REPO when not present locally
+CONFIG_PATH = Path("models/config.json")
+MODEL_REPO = "hexgrad/Kokoro-82M"
 VOICE_DIR = Path("models/voices")
+# Extra voices (--voices-dir or KOKORO_VOICES_DIR), overriding VOICE_DIR's on name clashes
+voices_dir: Optional[Path] = Path(os.environ["KOKORO_VOICES_DIR"]) if os.environ.get("KOKORO_VOICES_DIR") else None
+LANGUAGES_PATH = Path("models/languages.json")
+DEFAULT_VOICES_PATH = Path("models/default_voices.json")
+
+# Kokoro always renders 24 kHz mono float audio
+SAMPLE_RATE = 24000
+
+# Output resampler: sinc zero crossings on each side of a kernel, Kaiser
+# window shape (~80 dB stopband) and output samples computed per block
+RESAMPLE_ZERO_CROSSINGS = 16
+RESAMPLE_KAISER_BETA = 8.6
+RESAMPLE_BLOCK = 16384
+# Pitch changes use the same resampler, with the playback rate rounded to
+# 1/PLAYBACK_RATE_STEPS (well under a cent) to keep its kernel phases few
+PLAYBACK_RATE_STEPS = 1000
+
+# Longest request text accepted (characters); set with --max-text-length or
+# KOKORO_MAX_TEXT_LENGTH. Longer texts get 413 and should be split by the client.
+MAX_TEXT_LENGTH = int(os.environ.get("KOKORO_MAX_TEXT_LENGTH", "5000"))
+# Most texts one /synthesize/batch or /warmup request may carry
+MAX_BATCH_ITEMS = 32
+
+# Longest a generation pass may run (seconds, KOKORO_MAX_SYNTHESIS_S) before
+# it is abandoned with 504; checked between pipeline segments
+MAX_SYNTHESIS_S = float(os.environ.get("KOKORO_MAX_SYNTHESIS_S", "120"))
+
+# How long a finished job and its audio are kept for fetching (seconds, KOKORO_JOB_TTL_S)
+JOB_TTL_S = float(os.environ.get("KOKORO_JOB_TTL_S", "3600"))
+# Most jobs queued or running at once (KOKORO_MAX_PENDING_JOBS); each holds a
+# worker thread, so beyond this POST /jobs answers 503 instead of starving the pool
+MAX_PENDING_JOBS = int(os.environ.get("KOKORO_MAX_PENDING_JOBS", "8"))
+
+# Speaking rates a request may ask for; combined with per-run and random
+# factors, the rate given to the model is clamped to the same range
+SPEED_RANGE = (0.25, 4.0)
+
+# Longest phoneme sequence the model takes in one pass
+MAX_PHONEMES = 510
+
+# Average length of a phoneme at speed 1.0 (symbols, stress marks and spaces
+# included), used by /estimate in place of the model's duration predictor
+ESTIMATE_PHONEME_S = 0.075
+
+# Voice packs hold one style vector per input length; its first half styles
+# the decoder (timbre), the second the duration/prosody predictor
+STYLE_DIM = 256
+
+# Closest Kokoro voice for each OpenAI voice; used when a client asks for an
+# OpenAI voice name rather than an installed one
+OPENAI_VOICES = {
+    "alloy": "af_alloy", "ash": "am_adam", "ballad": "bm_george", "coral": "af_heart",
+    "echo": "am_echo", "fable": "bm_fable", "onyx": "am_onyx", "nova": "af_nova",
+    "sage": "af_sarah", "shimmer": "af_bella", "verse": "am_michael",
+}
+
+# Upper bounds (seconds) of the synthesis latency histogram buckets
+LATENCY_BUCKETS = (0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0)
+
+# Upper bound on the audio held by the render cache
+CACHE_MAX_BYTES = 256 * 1024 * 1024
+
+# How often job progress streams check for news (seconds)
+PROGRESS_INTERVAL_S = 0.25
+
+# How long clients and proxies may reuse a reproducible audio file (seconds)
+FILE_MAX_AGE_S = 86400
+
+# Overlap when joining separately generated segments (8 ms)
+CROSSFADE_SAMPLES = SAMPLE_RATE // 125
+
+# What each request quality_level spends time on: band-limited (sinc) rather
+# than linear resampling for pitch and output rate, crossfaded segment joins,
+# and vibrato. Only post-processing gets cheaper; the model pass is the same.
+QUALITY_LEVELS = {
+    "high": {"sinc": True, "crossfade": True, "vibrato": True},
+    "medium": {"sinc": True, "crossfade": True, "vibrato": False},
+    "low": {"sinc": False, "crossfade": False, "vibrato": False},
+}
+
+# Largest per-utterance perturbations applied at variation=1.0
+MAX_TEMPO_VARIATION = 0.06      # +-6% speaking rate
+MAX_PITCH_VARIATION = 0.5       # +-0.5 semitones
+
+# WAVE_FORMAT_EXTENSIBLE speaker masks by channel count. Layouts without a
+# standard speaker assignment (e.g. multitrack) get 0, i.e. "not positioned".
+CHANNEL_MASKS = {
+    1: 0x4,    # front center
+    2: 0x3,    # front left | front right
+    4: 0x33,   # front left/right, back left/right
+    6: 0x3F,   # 5.1
+}
+# WAV format tags; the G.711 (telephony) codecs are 8-bit and always 8 kHz
+WAVE_FORMAT_PCM, WAVE_FORMAT_ALAW, WAVE_FORMAT_MULAW = 1, 6, 7
+TELEPHONY_SAMPLE_RATE = 8000
+# KSDATAFORMAT_SUBTYPE_PCM
+PCM_SUBFORMAT_GUID = b"\x01\x00\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71"
+
+# Output sample rates a request may ask for; audio is resampled from SAMPLE_RATE
+OUTPUT_SAMPLE_RATES = (8000, 16000, 22050, 24000, 44100, 48000)
+# The ones libsndfile's Ogg/Opus writer accepts
+OPUS_SAMPLE_RATES = tuple(rate for rate in OUTPUT_SAMPLE_RATES if rate in (8000, 12000, 16000, 24000, 48000))
+
+# Constant bitrates LAME offers (kbps) by lowest sample rate of each MPEG
+# version: MPEG-1 from 32 kHz, MPEG-2 from 16 kHz, MPEG-2.5 below
+MP3_BITRATE_RANGES = {32000: (32, 320), 16000: (8, 160), 0: (8, 64)}
+
+# Pause detection: 10 ms frames whose RMS is below this count as silence
+PAUSE_FRAME = SAMPLE_RATE // 100
+PAUSE_THRESHOLD = 0.01
+
+# Kokoro predicts durations in frames of 600 samples (1/40 s at 24 kHz)
+FRAME_SAMPLES = 600
+# Phoneme symbols that mark a boundary rather than a sound
+SILENT_PHONEMES = set(" ,.!?;:—…\"()“”")
+
+# Vowel symbols across the misaki and espeak phoneme sets (A/I/O/W/Y/Q are
+# misaki's diphthongs), and marks that modify a neighbour rather than sound
+VOWEL_PHONEMES = set("aeiouyæɑɒɔəɛɜɪʊʌᵻɐɚɝɨʉɯɤɘɵɞøœɶAIOWYQ")
+MODIFIER_PHONEMES = set("ˈˌːˑʰʲʷ̃")
+# Ramp length used when changing the level of individual phonemes
+PHONEME_RAMP = SAMPLE_RATE // 200
+
+# Level changes applied to emphasized and de-emphasized rich-text runs
+EMPHASIS_GAIN = 1.25
+DEEMPHASIS_GAIN = 0.5
+
+# Audio kept around the speech when trimming leading/trailing silence
+TRIM_MARGIN_MS = 10
+
+# Corner frequency of the DC blocker, well below any voice's fundamental
+DC_BLOCK_HZ = 20.0
+
+# Level above which the soft limiter bends peaks towards (never reaching) full scale
+SOFT_LIMIT_THRESHOLD = 0.8
+
+# Synthetic breaths: band-limited noise, shaped to a quick rise and slower fall,
+# with a little silence either side
+BREATH_MS = 320
+BREATH_BAND_HZ = (400, 3000)
+BREATH_GAP_MS = 60
+
+# Harvard sentences (list 1): phonetically balanced prompts for voice QA
+TEST_SENTENCES = [
+    "The birch canoe slid on the smooth planks.",
+    "Glue the sheet to the dark blue background.",
+    "It's easy to tell the depth of a well.",
+    "These days a chicken leg is a rare dish.",
+    "Rice is often served in round bowls.",
+    "The juice of lemons makes fine punch.",
+    "The box was thrown beside the parked truck.",
+    "The hogs were fed chopped corn and garbage.",
+    "Four hours of steady work faced us.",
+    "A large size in stockings is hard to sell.",
+]
+
+# Voice names start with a language prefix (af_sky -> "a"). Each prefix maps
+# to the KPipeline lang_code whose G2P supplies the phoneme set, plus the
+# normalize.RULESETS entry applied to the text beforehand. English has the full
+# misaki G2P; the others go through espeak. Entries in LANGUAGES_PATH override
+# or extend these, so adding a language is a matter of adding
+# {"<prefix>": {"lang_code": ..., "code": ..., "normalizer": ...}}. `code` is
+# the language tag requests use to select a default voice; `num2words` is the
+# language used to spell out numbers.
+DEFAULT_LANGUAGES = {
+    "a": {"lang_code": "a", "code": "en-us", "name": "American English", "normalizer": "en", "num2words": "en"},
+    "b": {"lang_code": "b", "code": "en-gb", "name": "British English", "normalizer": "en", "num2words": "en"},
+    "e": {"lang_code": "e", "code": "es", "name": "Spanish", "num2words": "es"},
+    "f": {"lang_code": "f", "code": "fr", "name": "French", "num2words": "fr"},
+    "h": {"lang_code": "h", "code": "hi", "name": "Hindi"},
+    "i": {"lang_code": "i", "code": "it", "name": "Italian", "num2words": "it"},
+    "p": {"lang_code": "p", "code": "pt-br", "name": "Brazilian Portuguese", "num2words": "pt_BR"},
+}
 
 # Pydantic models for settings
 class Settings(BaseModel):
@@ -23,26 +284,1838 @@ class Settings(BaseModel):
 class SettingsOut(Settings):
     available_voices: List[str]
 
+# Pydantic model for synthesis requests
+class SynthesisRequest(BaseModel):
+    text: str = ""                    # Plain text, or SSML when it starts with <speak>
+    phonemes: Optional[str] = None    # IPA to speak instead of `text`, in the model's phoneme set (skips G2P)
+    voice: Optional[str] = None       # Default: the language's default voice, else settings.voice
+    voice2: Optional[str] = None      # Mix in a second voice, by `blend`
+    blend: float = Field(0.5, ge=0.0, le=1.0)  # 0.0 = `voice` only, 1.0 = `voice2` only
+    # Language tag ("en", "en-gb", "es", ...) of the text: selects its G2P and
+    # normalization rules, and the default voice. Default: the voice's language.
+    language: Optional[str] = None
+    speed: float = Field(1.0, ge=SPEED_RANGE[0], le=SPEED_RANGE[1])  # Rejects 0, negatives, inf and NaN
+    pitch: float = Field(1.0, ge=0.5, le=2.0)  # Pitch factor for the whole voice; 1.0 leaves it unchanged
+    variation: float = Field(0.0, ge=0.0, le=1.0)  # Random tempo/pitch drift per request, 0 = none
+    seed: Optional[int] = Field(None, ge=0, lt=2 ** 63)  # Fix all randomness (variation, breaths, vocoder noise)
+    consonant_boost: float = Field(1.0, ge=0.25, le=4.0)  # Level of consonants relative to vowels
+    inter_word_pause_ms: float = Field(0.0, ge=0.0, le=2000.0)  # Extra silence between words (English voices)
+    normalize: bool = True            # Rewrite the text for reading (abbreviations, numbers); False sends it as is
+    split_camel_case: bool = False    # Read "getUserName" as "get user name"
+    # How to read numbers: as quantities, digit by digit, or digits only for
+    # phone numbers/codes. Default: left to the language's G2P.
+    number_mode: Optional[Literal["cardinal", "digits", "auto"]] = None
+    parentheticals: bool = False      # Read "(asides)" quieter, set off by pauses
+    parenthetical_pause_ms: float = Field(150.0, ge=0.0, le=2000.0)
+    breaths: bool = False             # Insert a soft inhalation between sentences
+    breath_db: float = Field(-32.0, ge=-60.0, le=-10.0)  # Peak level of the breaths (dBFS)
+    max_pause_ms: Optional[float] = Field(None, ge=0.0, allow_inf_nan=False)  # Cap every silence at this length
+    pre_emphasis: Optional[float] = Field(None, ge=0.0, lt=1.0)  # High-frequency boost coefficient, e.g. 0.97
+    remove_dc: bool = True            # High-pass out any DC offset (before loudness normalization)
+    vibrato_depth: float = Field(0.0, ge=0.0, le=2.0)  # Pitch swing either way, semitones; 0 = none
+    vibrato_rate: float = Field(5.5, ge=1.0, le=12.0)  # Swings per second
+    # Attenuate audio quieter than this level (dBFS), e.g. -50
+    noise_gate_db: Optional[float] = Field(None, ge=-120.0, le=0.0, allow_inf_nan=False)
+    gate_attack_ms: float = Field(5.0, gt=0.0, le=1000.0, allow_inf_nan=False)    # How fast the gate opens when speech starts
+    gate_release_ms: float = Field(80.0, gt=0.0, le=5000.0, allow_inf_nan=False)  # How fast it closes again once it falls below the threshold
+    trim_silence: bool = False        # Cut leading/trailing audio quieter than `silence_threshold_db`
+    silence_threshold_db: float = Field(-50.0, ge=-100.0, le=0.0)
+    normalize_loudness: bool = False  # Scale the output to `target_lufs` integrated loudness
+    target_lufs: float = Field(-23.0, ge=-70.0, le=0.0)  # EBU R128 broadcast level by default
+    # Final volume change (after loudness normalization). Peaks pushed past full
+    # scale are rounded off by `soft_limit`, or without it clamped by the encoders
+    # (and counted by `quality`).
+    gain_db: float = Field(0.0, ge=-40.0, le=24.0)
+    soft_limit: bool = True           # Saturate peaks smoothly instead of hard clipping them
+    # Trade post-processing quality for speed, e.g. "low" for batch previews (see QUALITY_LEVELS)
+    quality_level: Literal["low", "medium", "high"] = "high"
+    start_s: Optional[float] = Field(None, ge=0.0, allow_inf_nan=False)  # Only return audio from this time (seconds)
+    end_s: Optional[float] = Field(None, ge=0.0, allow_inf_nan=False)    # Only return audio up to this time (seconds)
+    format: str = "wav"               # One of the formats listed by /status, or "auto" (see below);
+                                      # "mulaw" and "alaw" are 8 kHz G.711 WAVs whatever sample_rate says
+    # With format "auto": WAV for clips up to this long, the compressed format beyond
+    auto_format_threshold_s: float = Field(10.0, ge=0.0, le=3600.0, allow_inf_nan=False)
+    # Only formats that keep sample_rate; the G.711 ones would switch a long clip to 8 kHz
+    auto_compressed_format: Literal["mp3", "ogg", "opus", "flac"] = "mp3"
+    bit_depth: Literal[16, 24] = 16
+    bitrate: int = Field(128, ge=8, le=320)  # MP3 only, kbps (limited to what the sample rate allows)
+    sample_rate: Optional[int] = None  # One of OUTPUT_SAMPLE_RATES; default 24000
+    channels: Literal[1, 2] = 1
+    pan: float = Field(0.0, ge=-1.0, le=1.0)  # Stereo only: -1.0 left, 0.0 center (both full), 1.0 right
+    id3_tags: bool = False            # MP3 only: tag title (the text) and comment (voice, speed)
+    explain: bool = False             # Include a report of text normalizations (/synthesize)
+    quality: bool = False             # Include a clipping report (/synthesize)
+    base64url: bool = False           # URL-safe, unpadded base64 for audio_data (/synthesize)
+
+class QualityReport(BaseModel):
+    clipped_samples: int              # Samples beyond +-1.0 that were clamped on output
+    peak: float                       # Largest absolute sample value before clamping
+
+# Rich-text documents: paragraphs (each with its own voice) made of styled runs
+class RichRun(BaseModel):
+    text: str
+    emphasis: bool = False                       # Stressed (English) and slightly louder
+    deemphasis: bool = False                     # Destressed (English) and quieter, for asides
+    pitch: float = Field(1.0, ge=0.5, le=2.0)    # Pitch factor for this run
+    speed: float = Field(1.0, ge=0.25, le=4.0)   # Speed factor for this run
+    break_ms: float = Field(0.0, ge=0.0, le=10000.0)  # Pause after the run
+
+class RichParagraph(BaseModel):
+    voice: Optional[str] = None                  # Default: settings.voice
+    runs: List[RichRun]
+
+class RichDocument(BaseModel):
+    paragraphs: List[RichParagraph]
+    speed: float = Field(1.0, ge=SPEED_RANGE[0], le=SPEED_RANGE[1])
+    paragraph_break_ms: float = Field(600.0, ge=0.0, le=10000.0)
+
+class VoicePoint(BaseModel):
+    name: str
+    x: float
+    y: float
+
+class PitchEstimate(BaseModel):
+    median_hz: float
+    min_hz: float                     # 5th percentile of voiced frames
+    max_hz: float                     # 95th percentile of voiced frames
+
+class VoiceInfo(BaseModel):
+    """What a voice's name says about it (see voice_info); "unknown" where it says nothing."""
+    name: str
+    language: str                     # e.g. "American English"
+    accent: str                       # Language tag, e.g. "en-us"
+    gender: str
+    speaker: str                      # e.g. "sky" for af_sky
+
+class LanguageInfo(BaseModel):
+    code: str                         # Tag accepted as `language`
+    name: str
+    lang_code: str                    # Kokoro pipeline (and voice name prefix)
+    default_voice: Optional[str] = None
+
+class VoiceDetails(VoiceInfo):
+    embedding_shape: List[int]
+    embedding_length: int             # Total values in the pack
+    well_formed: bool                 # Shape is (max phonemes, 1, STYLE_DIM), what the model expects
+    timbre_norm: float                # Norm of the averaged decoder (timbre) half of the style vector
+    prosody_norm: float               # Norm of the averaged prosody-predictor half
+
+class VoiceProfile(VoiceInfo):
+    embedding_shape: List[int]
+    pitch: Optional[PitchEstimate]    # None if no voiced frames were found in the sample
+    sample_text: str
+    sample_audio: str                 # Base64-encoded WAV of sample_text
+
+class JobStatus(BaseModel):
+    id: str
+    status: Literal["queued", "running", "done", "failed"]
+    format: str
+    error: Optional[str] = None
+    error_code: Optional[str] = None
+    size_bytes: Optional[int] = None    # Encoded audio, once done
+    progress: float = 0.0               # Share of the text synthesized, 0-1
+    stored_bytes: Optional[int] = None  # The same audio as held gzip-compressed
+
+class SynthesisResponse(BaseModel):
+    audio_data: str                   # Base64-encoded audio file (URL-safe and unpadded with `base64url`)
+    format: str
+    sample_rate: int
+    duration_seconds: float
+    normalizations: Optional[List[Normalization]] = None  # Only with `explain`
+    quality: Optional[QualityReport] = None               # Only with `quality`
+    cache_hit: bool = False                               # Served from the render cache
+
+class SpeechRequest(BaseModel):
+    """Request body of OpenAI's POST /v1/audio/speech."""
+    model: str = "kokoro"             # Accepted for compatibility; there is only one model
+    input: str
+    voice: str = "alloy"              # An OpenAI voice name, or any installed voice
+    response_format: Literal["mp3", "opus", "aac", "flac", "wav", "pcm"] = "mp3"
+    speed: float = Field(1.0, ge=SPEED_RANGE[0], le=SPEED_RANGE[1])
+
+class TokenizeRequest(BaseModel):
+    text: str
+    voice: Optional[str] = None       # Selects the G2P language, as for synthesis
+    language: Optional[str] = None
+
+class Token(BaseModel):
+    id: Optional[int]                 # None for symbols the model would drop
+    symbol: str
+    label: str
+
+class WordToken(BaseModel):
+    text: str
+    phonemes: Optional[str]
+
+class TokenizedChunk(BaseModel):
+    graphemes: str
+    phonemes: str
+    tokens: List[Token]               # Model input IDs, including the boundary tokens
+    words: Optional[List[WordToken]] = None  # English G2P only
+
+class SpectrumRequest(SynthesisRequest):
+    bins: int = Field(256, ge=8, le=4096)  # Frequency bands the spectrum is averaged into
+
+class Spectrum(BaseModel):
+    sample_rate: int
+    bin_width_hz: float
+    magnitudes_db: List[float]        # Mean power per band, dB relative to a full-scale sine
+    peak_hz: float                    # Strongest frequency at full FFT resolution (DC excluded)
+
+class Estimate(BaseModel):
+    duration_seconds: float
+    samples: int                      # At sample_rate
+    sample_rate: int
+    phonemes: int                     # Symbols the model would be given
+
+class WarmupRequest(BaseModel):
+    phrases: List[str] = Field(min_length=1, max_length=MAX_BATCH_ITEMS)
+    # The options the phrases will be requested with (cache hits need identical
+    # requests); its `text` is replaced by each phrase
+    options: SynthesisRequest = Field(default_factory=SynthesisRequest)
+
+class WarmupResult(BaseModel):
+    warmed: int
+    cache_bytes: int                  # Audio held by the render cache afterwards
+
+class BatchRequest(BaseModel):
+    items: List[SynthesisRequest] = Field(min_length=1, max_length=MAX_BATCH_ITEMS)
+
+class BatchResult(BaseModel):
+    index: int                        # Position of the item in the request
+    status_code: int = 200
+    result: Optional[SynthesisResponse] = None  # On success
+    error: Optional[str] = None                 # On failure
+    error_code: Optional[str] = None
+
 # Ensure core model exists
 if not CORE_MODEL_PATH.exists():
     raise RuntimeError(f"Core model not found at {CORE_MODEL_PATH}")
 
+def load_model_config() -> dict:
+    """The model's config.json: CONFIG_PATH if present, else MODEL_REPO's (as KModel would fetch it)."""
+    if CONFIG_PATH.exists():
+        return json.loads(CONFIG_PATH.read_text())
+    from huggingface_hub import hf_hub_download  # installed with kokoro
+    return json.loads(Path(hf_hub_download(repo_id=MODEL_REPO, filename="config.json")).read_text())
+
+def load_model(config: dict) -> KModel:
+    return KModel(repo_id=MODEL_REPO, config=config, model=str(CORE_MODEL_PATH))
+
 # Initialize shared core model for reuse
-core_model = KModel(model=str(CORE_MODEL_PATH))
+model_config = load_model_config()
+core_model = load_model(model_config)
+# Bumped by /reload, so ETags of files made by an earlier model stop matching
+model_generation = 0
+
+class SerializedG2P:
+    """
+    A pipeline's G2P behind g2p_lock. Passes share the model, which is safe
+    to call from several threads, but not G2P: misaki's spaCy models, and
+    espeak-ng, one process-wide engine behind every non-English pipeline.
+    """
+
+    def __init__(self, g2p):
+        self.g2p = g2p
+
+    def __call__(self, *args, **kwargs):
+        with g2p_lock:
+            return self.g2p(*args, **kwargs)
+
+    def __getattr__(self, name):
+        return getattr(self.g2p, name)
+
+g2p_lock = threading.Lock()
+
+def serialize_g2p(pipeline: KPipeline) -> KPipeline:
+    if getattr(pipeline, "g2p", None) is not None:
+        pipeline.g2p = SerializedG2P(pipeline.g2p)
+    return pipeline
 
 # Function to create a new pipeline given language code
 def create_pipeline(lang_code: str) -> KPipeline:
-    return KPipeline(model=core_model, lang_code=lang_code)
+    return serialize_g2p(KPipeline(model=core_model, lang_code=lang_code))
+
+class SynthesisLock:
+    """
+    Readers-writer lock around the model. Inference only reads it, so
+    generation passes share the lock and run in parallel; /reload (which
+    swaps the model) and seeded passes (whose noise goes through torch's
+    default generator, see seeded_noise) hold it exclusively. Waiting exclusive holders keep new passes from starting.
+    """
+
+    def __init__(self):
+        self.condition = threading.Condition()
+        self.readers = 0
+        self.writing = False
+        self.waiting_writers = 0
+
+    @contextmanager
+    def shared(self):
+        with self.condition:
+            while self.writing or self.waiting_writers:
+                self.condition.wait()
+            self.readers += 1
+        try:
+            yield
+        finally:
+            with self.condition:
+                self.readers -= 1
+                self.condition.notify_all()
+
+    @contextmanager
+    def exclusive(self):
+        with self.condition:
+            self.waiting_writers += 1
+            while self.writing or self.readers:
+                self.condition.wait()
+            self.waiting_writers -= 1
+            self.writing = True
+        try:
+            yield
+        finally:
+            with self.condition:
+                self.writing = False
+                self.condition.notify_all()
+
+# Background jobs, streams and most renders run in worker threads
+synthesis_lock = SynthesisLock()
+pipelines_lock = threading.Lock()  # guards lazy pipeline creation
+
+def load_languages() -> Dict[str, dict]:
+    """Merge the built-in language table with overrides from LANGUAGES_PATH."""
+    languages = dict(DEFAULT_LANGUAGES)
+    if LANGUAGES_PATH.exists():
+        languages.update(json.loads(LANGUAGES_PATH.read_text()))
+    return languages
+
+def load_default_voices() -> Dict[str, str]:
+    """Per-language default voices, e.g. {"en": "af_sarah", "es": "ef_dora"}."""
+    if DEFAULT_VOICES_PATH.exists():
+        return json.loads(DEFAULT_VOICES_PATH.read_text())
+    return {}
 
-# Global settings and pipeline
+# Global settings, language table and pipelines (one per lang_code, created lazily)
 settings = Settings()
-pipeline = create_pipeline(settings.lang_code)
+languages = load_languages()
+default_voices = load_default_voices()
+pipelines: Dict[str, KPipeline] = {}
+g2p_pipelines: Dict[str, KPipeline] = {}
+
+def get_pipeline(lang_code: str) -> KPipeline:
+    with pipelines_lock:
+        if lang_code not in pipelines:
+            pipelines[lang_code] = create_pipeline(lang_code)
+        return pipelines[lang_code]
+
+def get_g2p_pipeline(lang_code: str) -> KPipeline:
+    """A pipeline without a model, for phonemizing only."""
+    with pipelines_lock:
+        if lang_code not in g2p_pipelines:
+            g2p_pipelines[lang_code] = serialize_g2p(KPipeline(lang_code=lang_code, model=False))
+        return g2p_pipelines[lang_code]
+
+def language_for_voice(voice: str) -> Optional[dict]:
+    return languages.get(Path(voice).name[:1])
+
+def language_entry(voice: str, language: Optional[str] = None) -> Optional[dict]:
+    """
+    The language table entry whose G2P reads a text: that of `language` if
+    given (a full tag, or a base language meaning its first variant, e.g.
+    "en" for "en-us"), else the one of the voice's prefix. The voice keeps its
+    timbre either way, so e.g. an English voice can read Spanish text.
+    """
+    if language is None:
+        return language_for_voice(voice)
+    language = language.lower()
+    entries = [e for e in languages.values() if e.get("code") == language]
+    entries = entries or [e for e in languages.values() if e.get("code", "").split("-")[0] == language]
+    if not entries:
+        raise APIError(400, "UNSUPPORTED_LANGUAGE",
+                       f"Language '{language}' not supported. Available: {language_codes()}")
+    return entries[0]
+
+def pipeline_for_voice(voice: str, language: Optional[str] = None) -> KPipeline:
+    """
+    Select the pipeline of `language`, else the one matching the voice's
+    language prefix, falling back to the configured lang_code for voices with
+    an unknown prefix.
+    """
+    entry = language_entry(voice, language)
+    return get_pipeline(entry["lang_code"] if entry else settings.lang_code)
+
+def g2p_pipeline_for_voice(voice: str, language: Optional[str] = None) -> KPipeline:
+    """The model-less counterpart of pipeline_for_voice, for phonemizing only."""
+    entry = language_entry(voice, language)
+    return get_g2p_pipeline(entry["lang_code"] if entry else settings.lang_code)
+
+def language_codes() -> List[str]:
+    """All language tags accepted in requests: full codes and their base languages."""
+    codes = {entry["code"] for entry in languages.values() if "code" in entry}
+    return sorted(codes | {code.split("-")[0] for code in codes})
+
+def default_voice(language: Optional[str]) -> str:
+    """
+    Voice to use when a request names none: the default voice mapped for its
+    language (or that language's base, "en-gb" -> "en"), else settings.voice.
+    """
+    if language is None:
+        return settings.voice
+    language = language.lower()
+    if language not in language_codes():
+        raise APIError(400, "UNSUPPORTED_LANGUAGE",
+                       f"Language '{language}' not supported. Available: {language_codes()}")
+    return default_voices.get(language) or default_voices.get(language.split("-")[0]) or settings.voice
+
+def prepare_text(text: str, voice: str, req: Optional["SynthesisRequest"] = None,
+                 language: Optional[str] = None) -> tuple[str, List[Normalization]]:
+    """
+    Apply the normalization rules of the text's language (the request's, else
+    `language`, else the voice's) and the request's options.
+    """
+    if req is not None and not req.normalize:
+        return text, []
+    language = language_entry(voice, req.language if req else language) or {}
+    return normalize_text(
+        text, language.get("normalizer"),
+        split_camel_case_words=req.split_camel_case if req else False,
+        number_mode=req.number_mode if req else None,
+        number_lang=language.get("num2words"),
+    )
+
+def voice_files() -> Dict[str, Path]:
+    """Installed voice packs by name: those in VOICE_DIR, then voices_dir's on top."""
+    files = {}
+    for directory in (VOICE_DIR, voices_dir):
+        if directory is not None and directory.exists():
+            files.update({p.stem: p for p in directory.glob("*.pt")})
+    return files
 
 def get_available_voices() -> List[str]:
-    """Scan VOICE_DIR for .pt files and return filenames."""
-    if not VOICE_DIR.exists():
-        return []
-    return [p.name for p in VOICE_DIR.glob("*.pt")]
+    """Filenames (with .pt) of the installed voices."""
+    return sorted(p.name for p in voice_files().values())
+
+def report_voices():
+    """Log how many voices each directory contributes."""
+    for directory in (VOICE_DIR, voices_dir):
+        if directory is not None:
+            count = len(list(directory.glob("*.pt"))) if directory.exists() else 0
+            logger.info("%d voices in %s", count, directory)
+    if not voice_files():
+        logger.warning("No voices found: synthesis requests will be refused with 503")
+
+def check_default_voice():
+    """Fall back to the first installed voice when settings.voice is not installed."""
+    installed = sorted(voice_files())
+    if installed and Path(settings.voice).stem not in installed:
+        logger.warning("Default voice '%s' is not installed, using '%s'", settings.voice, installed[0])
+        settings.voice = installed[0]
+
+report_voices()
+check_default_voice()
+
+def load_voice_embedding(path: Path) -> np.ndarray:
+    """
+    Load a voice pack (one 256-dim style vector per input length) and average
+    it into a single vector that characterizes the voice.
+    """
+    pack = torch.load(path, map_location="cpu", weights_only=True)
+    return pack.reshape(-1, pack.shape[-1]).mean(dim=0).numpy()
+
+def voice_info(name: str) -> dict:
+    """
+    Parse the naming convention <language prefix><gender>_<speaker> (af_sky is
+    an American English female voice) into structured fields. Names that don't
+    follow it get "unknown" for everything they don't tell.
+    """
+    prefix, _, speaker = name.partition("_")
+    conventional = len(prefix) == 2 and bool(speaker)
+    language = language_for_voice(name) if conventional else None
+    return {
+        "language": language["name"] if language else "unknown",
+        "accent": language.get("code", "unknown") if language else "unknown",
+        "gender": {"f": "female", "m": "male"}.get(prefix[1:], "unknown") if conventional else "unknown",
+        "speaker": speaker if conventional else "unknown",
+    }
+
+def estimate_pitch(audio: np.ndarray) -> Optional[PitchEstimate]:
+    """
+    Autocorrelation F0 estimate over 40 ms frames, searching 50-500 Hz and
+    keeping frames that are loud and clearly periodic.
+    """
+    frame, hop = SAMPLE_RATE // 25, SAMPLE_RATE // 100
+    min_lag, max_lag = SAMPLE_RATE // 500, SAMPLE_RATE // 50
+    f0 = []
+    for start in range(0, len(audio) - frame, hop):
+        x = audio[start:start + frame] - np.mean(audio[start:start + frame])
+        if np.sqrt(np.mean(x ** 2)) < PAUSE_THRESHOLD:
+            continue
+        corr = np.correlate(x, x, mode="full")[frame - 1:]
+        lag = min_lag + int(np.argmax(corr[min_lag:max_lag]))
+        if corr[lag] > 0.5 * corr[0]:
+            f0.append(SAMPLE_RATE / lag)
+    if not f0:
+        return None
+    low, median, high = np.percentile(f0, [5, 50, 95])
+    return PitchEstimate(median_hz=float(median), min_hz=float(low), max_hz=float(high))
+
+def resolve_voice(voice: str) -> str:
+    """
+    The installed file of a voice name (with or without .pt). Only these are
+    ever loaded; any other name, even of an existing file, is INVALID_VOICE.
+    """
+    check_voice(voice)
+    return str(voice_files()[Path(voice).stem])
+
+def blend_voices(a: str, b: str, t: float) -> torch.Tensor:
+    """Linearly interpolate two voice packs: 0.0 gives `a`, 1.0 gives `b`."""
+    check_voice(a)
+    check_voice(b)
+    pack_a, pack_b = (torch.load(resolve_voice(v), map_location="cpu", weights_only=True) for v in (a, b))
+    if pack_a.shape != pack_b.shape:
+        raise APIError(400, "INVALID_VOICE", f"Voices '{a}' and '{b}' have different embedding shapes")
+    return torch.lerp(pack_a, pack_b, t)
+
+def check_text_length(length: int):
+    if length > MAX_TEXT_LENGTH:
+        raise APIError(413, "TEXT_TOO_LONG",
+                       f"Text is {length} characters; the maximum is {MAX_TEXT_LENGTH}")
+
+def check_voice(voice: str):
+    if not get_available_voices():
+        raise APIError(503, "NO_VOICES_LOADED", "No voices installed; synthesis is unavailable")
+    if Path(voice).stem not in voice_files():
+        raise APIError(400, "INVALID_VOICE", f"Voice '{voice}' not found. Available: {get_available_voices()}")
+
+def change_playback_rate(audio: np.ndarray, factor: float, sinc: bool = True) -> np.ndarray:
+    """
+    Resample so the audio plays `factor` times faster, raising pitch by the
+    same factor. This goes through resample(), so content pushed past the
+    Nyquist frequency is filtered out instead of aliasing; without `sinc`
+    through the cheaper resample_linear(), which does alias.
+    """
+    if factor == 1.0 or len(audio) < 2:
+        return audio
+    return (resample if sinc else resample_linear)(audio, round(factor * PLAYBACK_RATE_STEPS), PLAYBACK_RATE_STEPS)
+
+def resample_linear(audio: np.ndarray, from_rate: int, to_rate: int) -> np.ndarray:
+    """Resampling by linear interpolation: fast, but without anti-aliasing (quality_level "low")."""
+    if from_rate == to_rate or not len(audio):
+        return audio
+    positions = np.arange(len(audio) * to_rate // from_rate) * (from_rate / to_rate)
+    return np.interp(positions, np.arange(len(audio)), audio).astype(np.float32)
+
+def resample_for(quality_level: str):
+    return resample if QUALITY_LEVELS[quality_level]["sinc"] else resample_linear
+
+def resample(audio: np.ndarray, from_rate: int, to_rate: int) -> np.ndarray:
+    """
+    Band-limited resampling by Kaiser-windowed sinc interpolation. The kernel
+    cuts off at the lower of the two Nyquist frequencies, so downsampling
+    does not alias. Rates are in a rational ratio up/down, so there are only
+    `up` distinct kernel phases; they are computed once per call.
+    """
+    if from_rate == to_rate or not len(audio):
+        return audio
+    g = math.gcd(from_rate, to_rate)
+    up, down = to_rate // g, from_rate // g
+    cutoff = min(1.0, up / down)  # relative to the input Nyquist frequency
+    half_width = RESAMPLE_ZERO_CROSSINGS / cutoff  # kernel half-length, in input samples
+    offsets = np.arange(-math.ceil(half_width) + 1, math.ceil(half_width) + 1)
+
+    # kernels[p][j] weighs input sample floor(t) + offsets[j] for an output at t = floor(t) + p / up
+    distance = np.arange(up)[:, None] / up - offsets[None, :]
+    inside = np.abs(distance) < half_width
+    window = np.i0(RESAMPLE_KAISER_BETA * np.sqrt(np.where(inside, 1 - (distance / half_width) ** 2, 0)))
+    kernels = np.where(inside, cutoff * np.sinc(cutoff * distance) * window / np.i0(RESAMPLE_KAISER_BETA), 0)
+
+    pad = len(offsets)
+    padded = np.pad(audio.astype(np.float64), pad)
+    positions = np.arange(len(audio) * up // down) * down
+    out = np.empty(len(positions), dtype=np.float32)
+    for start in range(0, len(positions), RESAMPLE_BLOCK):
+        block = positions[start:start + RESAMPLE_BLOCK]
+        taps = padded[(block // up)[:, None] + offsets + pad]
+        out[start:start + len(block)] = np.sum(taps * kernels[block % up], axis=1)
+    return out
+
+def to_channels(audio: np.ndarray, channels: int, pan: float = 0.0) -> np.ndarray:
+    """
+    Mono audio as-is, or as interleaved (frames, 2) stereo panned by
+    attenuating the far channel: at pan 0 both channels carry the full signal.
+    """
+    if channels == 1:
+        return audio
+    gains = np.array([min(1.0, 1.0 - pan), min(1.0, 1.0 + pan)], dtype=np.float32)
+    return audio[:, None] * gains
+
+def check_sample_rate(rate: Optional[int]):
+    if rate is not None and rate not in OUTPUT_SAMPLE_RATES:
+        raise APIError(400, "UNSUPPORTED_SAMPLE_RATE",
+                       f"Sample rate {rate} not supported. Available: {list(OUTPUT_SAMPLE_RATES)}")
+
+def apply_variation(speed: float, variation: float, rng: Optional[random.Random] = None) -> tuple[float, float]:
+    """Randomly perturb speed and pick a pitch factor, both scaled by `variation`."""
+    if variation <= 0:
+        return speed, 1.0
+    rng = rng or random.Random()
+    tempo = 1.0 + rng.uniform(-1, 1) * MAX_TEMPO_VARIATION * variation
+    semitones = rng.uniform(-1, 1) * MAX_PITCH_VARIATION * variation
+    return speed * tempo, 2 ** (semitones / 12)
+
+def silence(ms: float) -> np.ndarray:
+    return np.zeros(int(ms * SAMPLE_RATE / 1000), dtype=np.float32)
+
+def consonant_gain(result, length: int, boost: float) -> np.ndarray:
+    """
+    Per-sample gain for one pipeline result that scales consonants by `boost`
+    and leaves vowels, modifiers and silences alone.
+    """
+    gain = np.ones(length, dtype=np.float32)
+    if result.pred_dur is None:
+        return gain
+    durations = [int(d) * FRAME_SAMPLES for d in result.pred_dur]
+    cursor = durations[0]
+    for symbol, duration in zip(result.phonemes, durations[1:-1]):
+        if symbol not in VOWEL_PHONEMES | MODIFIER_PHONEMES | SILENT_PHONEMES:
+            gain[cursor:cursor + duration] = boost
+        cursor += duration
+    # Smooth the steps so level changes don't click
+    return np.convolve(gain, np.full(PHONEME_RAMP, 1 / PHONEME_RAMP), mode="same").astype(np.float32)
+
+def insert_word_pauses(audio: np.ndarray, tokens: list, pause_samples: int) -> np.ndarray:
+    """Insert silence at each word boundary, using the G2P token timestamps."""
+    cuts = sorted({
+        int(round(t.end_ts * SAMPLE_RATE))
+        for t in tokens[:-1]
+        if t.whitespace and t.end_ts is not None
+    })
+    pieces = np.split(audio, [c for c in cuts if 0 < c < len(audio)])
+    silence = np.zeros(pause_samples, dtype=np.float32)
+    return np.concatenate([p for piece in pieces for p in (piece, silence)][:-1])
+
+@dataclass
+class Alignment:
+    """Word and phoneme intervals, (start_s, end_s, label), over the whole output."""
+    words: List[tuple[float, float, str]] = field(default_factory=list)
+    phonemes: List[tuple[float, float, str]] = field(default_factory=list)
+
+    def add_segment(self, result, offset_s: float):
+        """Collect intervals from one pipeline result starting at `offset_s`."""
+        for t in result.tokens or []:
+            if t.start_ts is not None and t.end_ts is not None and any(c.isalnum() for c in t.text):
+                self.words.append((offset_s + t.start_ts, offset_s + t.end_ts, t.text))
+        if result.pred_dur is None:
+            return
+        durations = [int(d) * FRAME_SAMPLES / SAMPLE_RATE for d in result.pred_dur]
+        cursor = offset_s + durations[0]  # leading <bos> frames
+        for symbol, duration in zip(result.phonemes, durations[1:-1]):
+            label = "" if symbol in SILENT_PHONEMES else symbol
+            self.phonemes.append((cursor, cursor + duration, label))
+            cursor += duration
+
+    def scaled(self, factor: float) -> "Alignment":
+        return Alignment(
+            [(a * factor, b * factor, w) for a, b, w in self.words],
+            [(a * factor, b * factor, p) for a, b, p in self.phonemes],
+        )
+
+def ipa_to_phonemes(ipa: str) -> str:
+    """
+    Validate an IPA string against the model's phoneme vocabulary (which the
+    model would otherwise silently drop symbols outside of) and collapse its
+    whitespace.
+    """
+    phonemes = " ".join(ipa.split())
+    if not phonemes:
+        raise APIError(400, "EMPTY_TEXT", "`phonemes` is empty")
+    unknown = sorted({p for p in phonemes if p not in core_model.vocab})
+    if unknown:
+        raise APIError(400, "INVALID_PHONEMES", f"Unknown phoneme symbols: {' '.join(unknown)}")
+    return phonemes
+
+def phoneme_chunks(phonemes: str) -> List[str]:
+    """Split phonemes at spaces into pieces within the model's MAX_PHONEMES context."""
+    chunks, current = [], ""
+    for word in phonemes.split(" "):
+        if current and len(current) + 1 + len(word) > MAX_PHONEMES:
+            chunks.append(current)
+            current = ""
+        while len(word) > MAX_PHONEMES:
+            chunks.append(word[:MAX_PHONEMES])
+            word = word[MAX_PHONEMES:]
+        current = f"{current} {word}" if current else word
+    return chunks + [current] if current else chunks
+
+class SynthesisCancelled(Exception):
+    """The client disconnected while its audio was being generated."""
+
+# Per-thread state of the render running on it: its cancellation flag (see
+# render_until_disconnect) and a progress callback taking the characters done (see run_job)
+render_state = threading.local()
+
+def check_deadline(deadline: float):
+    """Abandon generation once it overruns MAX_SYNTHESIS_S or its client is gone."""
+    cancel = getattr(render_state, "cancel", None)
+    if cancel is not None and cancel.is_set():
+        raise SynthesisCancelled()
+    if time.monotonic() > deadline:
+        raise APIError(504, "SYNTHESIS_TIMEOUT", f"Synthesis took longer than {MAX_SYNTHESIS_S:g} s")
+
+@contextmanager
+def seeded_noise(generator: Optional[torch.Generator]):
+    """
+    Draw torch's random numbers from `generator` inside the block. Kokoro's
+    vocoder samples its noise from the default generator and takes no
+    generator of its own, so the pass's state is swapped in for the model call
+    and the global state restored afterwards; nothing else may draw from it
+    meanwhile, hence seeded passes hold synthesis_lock exclusively.
+    """
+    if generator is None:
+        yield
+        return
+    with torch.random.fork_rng(devices=[]):
+        torch.random.set_rng_state(generator.get_state())
+        try:
+            yield
+        finally:
+            generator.set_state(torch.random.get_rng_state())
+
+def pipeline_segments(text: str, voice: str, speed: float = 1.0, pitch: float = 1.0,
+                      word_pause_s: float = 0.0, consonant_boost: float = 1.0,
+                      phonemes: bool = False, blend: Optional[tuple[str, float]] = None,
+                      seed: Optional[int] = None, language: Optional[str] = None) -> Iterator[tuple]:
+    """
+    Yield (result, audio) for each pipeline segment as soon as it is generated.
+    The audio has consonant boost and word pauses applied, but not pitch: it is
+    generated at speed / pitch and still needs change_playback_rate(audio, pitch).
+    With `phonemes`, `text` is a validated phoneme string and G2P is skipped.
+    `blend` is (second voice, weight) to mix into `voice`; G2P stays that of
+    `voice`, or of `language` when given.
+    `seed` seeds the pass's own generator for the vocoder's noise so the output
+    is reproducible (see seeded_noise).
+    Generation stops after a segment if it has run for MAX_SYNTHESIS_S or the
+    render was cancelled.
+    """
+    pipeline = pipeline_for_voice(voice, language)
+    pack = blend_voices(voice, *blend) if blend else resolve_voice(voice)
+    rate = min(max(speed / pitch, SPEED_RANGE[0]), SPEED_RANGE[1])
+    if phonemes:
+        results = (result for chunk in phoneme_chunks(text)
+                   for result in pipeline.generate_from_tokens(chunk, voice=pack, speed=rate))
+    else:
+        results = pipeline(text, voice=pack, speed=rate)
+    generator = torch.Generator().manual_seed(seed) if seed is not None else None
+    deadline = None
+    while True:
+        # The lock covers one model call, never a yield: a slow stream reader mustn't hold it
+        with synthesis_lock.exclusive() if seed is not None else synthesis_lock.shared(), seeded_noise(generator):
+            if deadline is None:
+                deadline = time.monotonic() + MAX_SYNTHESIS_S  # the first wait for the lock doesn't count
+            result = next(results, None)
+        if result is None:
+            break
+        audio = np.asarray(result.audio, dtype=np.float32)
+        if consonant_boost != 1.0:
+            audio = audio * consonant_gain(result, len(audio), consonant_boost)
+        if word_pause_s > 0 and result.tokens:
+            audio = insert_word_pauses(audio, result.tokens, int(word_pause_s * SAMPLE_RATE * pitch))
+        yield result, audio
+        on_segment = getattr(render_state, "on_segment", None)
+        if on_segment is not None:
+            on_segment(len(result.graphemes or result.phonemes or ""))
+        check_deadline(deadline)
+
+def synthesize(text: str, voice: str, speed: float = 1.0, pitch: float = 1.0,
+               end_s: Optional[float] = None, word_pause_s: float = 0.0,
+               consonant_boost: float = 1.0, alignment: Optional[Alignment] = None,
+               phonemes: bool = False, blend: Optional[tuple[str, float]] = None,
+               seed: Optional[int] = None, language: Optional[str] = None,
+               quality_level: str = "high") -> np.ndarray:
+    """
+    Run the pipeline for `voice` and return the concatenated float audio.
+    A pitch factor other than 1.0 is applied by synthesizing slower and
+    resampling back, so duration stays that of `speed`. Generation stops once
+    `end_s` seconds are available, since later segments would be cut off anyway.
+    `word_pause_s` adds silence between words; it needs word timestamps, which
+    only the English pipelines provide. `consonant_boost` raises consonants
+    over vowels for intelligibility in noise. If `alignment` is given it is filled
+    with word/phoneme timings (word pauses are not reflected in it).
+    `quality_level` picks the pitch resampler and whether segments are crossfaded.
+    """
+    logger.debug("Synthesizing %d chars with voice %s (speed %.2f, pitch %.2f)", len(text), voice, speed, pitch,
+                 extra={"chars": len(text), "voice": voice, "speed": speed, "pitch": pitch})
+    started = time.perf_counter()
+    frames = []
+    length = 0
+    raw = Alignment()
+    quality = QUALITY_LEVELS[quality_level]
+    fade = CROSSFADE_SAMPLES if quality["crossfade"] else 0
+    segments = pipeline_segments(text, voice, speed, pitch, word_pause_s, consonant_boost, phonemes, blend, seed,
+                                 language)
+    for result, audio in segments:
+        overlap = min(fade, length, len(audio))
+        if alignment is not None:
+            raw.add_segment(result, (length - overlap) / SAMPLE_RATE)
+        frames.append(audio)
+        length += len(audio) - overlap
+        if end_s is not None and length >= end_s * SAMPLE_RATE * pitch:
+            segments.close()  # stop generating
+            break
+    audio_s, elapsed_s = length / SAMPLE_RATE / pitch, time.perf_counter() - started
+    logger.info("Generated %.2fs of audio in %.2fs", audio_s, elapsed_s,
+                extra={"voice": voice, "audio_seconds": round(audio_s, 3), "elapsed_seconds": round(elapsed_s, 3)})
+    metrics.generated(length)
+    if alignment is not None:
+        scaled = raw.scaled(1 / pitch)
+        alignment.words.extend(scaled.words)
+        alignment.phonemes.extend(scaled.phonemes)
+    return change_playback_rate(crossfade_join(frames, fade), pitch, quality["sinc"])
+
+def crossfade_join(pieces: List[np.ndarray], fade: int = CROSSFADE_SAMPLES) -> np.ndarray:
+    """
+    Concatenate audio pieces, overlapping each join by up to `fade` samples
+    with a raised-cosine crossfade so no join is a click-inducing step.
+    """
+    if not pieces:
+        return np.zeros(0, dtype=np.float32)
+    out = pieces[0]
+    for piece in pieces[1:]:
+        n = min(fade, len(out), len(piece))
+        if not n:
+            out = np.concatenate([out, piece])
+            continue
+        ramp = (0.5 - 0.5 * np.cos(np.linspace(0, np.pi, n))).astype(np.float32)
+        blended = out[-n:] * (1 - ramp) + piece[:n] * ramp
+        out = np.concatenate([out[:-n], blended, piece[n:]])
+    return out.astype(np.float32)
+
+def pre_emphasis(audio: np.ndarray, coefficient: float) -> np.ndarray:
+    """First-order pre-emphasis, y[n] = x[n] - a * x[n-1], to brighten speech."""
+    if not len(audio):
+        return audio
+    return np.append(audio[0], audio[1:] - coefficient * audio[:-1]).astype(np.float32)
+
+def apply_vibrato(audio: np.ndarray, rate_hz: float, depth_semitones: float) -> np.ndarray:
+    """
+    Sinusoidal pitch modulation: the audio is read back at a playback rate
+    swinging between 2^(±depth/12), normalized to average 1 so the duration
+    and the timing of every word stay as they were.
+    """
+    if not depth_semitones or len(audio) < 2:
+        return audio
+    t = np.arange(len(audio)) / SAMPLE_RATE
+    ratio = 2 ** (depth_semitones / 12 * np.sin(2 * np.pi * rate_hz * t))
+    ratio /= ratio.mean()
+    positions = np.minimum(np.cumsum(ratio) - ratio[0], len(audio) - 1)
+    return np.interp(positions, np.arange(len(audio)), audio).astype(np.float32)
+
+def soft_limit(audio: np.ndarray, threshold: float = SOFT_LIMIT_THRESHOLD) -> np.ndarray:
+    """
+    Leave samples within ±threshold untouched and compress the rest with a
+    tanh curve that meets them smoothly and approaches full scale without
+    exceeding it, so loud peaks saturate instead of being clipped harshly.
+    """
+    magnitude = np.abs(audio).astype(np.float64)
+    over = magnitude > threshold
+    if not np.any(over):
+        return audio
+    headroom = 1.0 - threshold
+    limited = threshold + headroom * np.tanh((magnitude - threshold) / headroom)
+    return np.where(over, np.sign(audio) * limited, audio).astype(np.float32)
+
+def remove_dc(audio: np.ndarray, cutoff_hz: float = DC_BLOCK_HZ) -> np.ndarray:
+    """
+    One-pole DC blocker, y[n] = x[n] - x[n-1] + r * y[n-1], with r set for a
+    -3 dB point near `cutoff_hz`. It is applied in the frequency domain, padded
+    by a second so the decaying tail does not wrap, which equals the recursion.
+    """
+    if not len(audio):
+        return audio
+    r = np.exp(-2 * np.pi * cutoff_hz / SAMPLE_RATE)
+    n = len(audio) + SAMPLE_RATE
+    delay = np.exp(-2j * np.pi * np.fft.rfftfreq(n))  # z^-1 on the unit circle
+    response = (1 - delay) / (1 - r * delay)
+    return np.fft.irfft(np.fft.rfft(audio, n) * response, n)[:len(audio)].astype(np.float32)
+
+def compress_pauses(audio: np.ndarray, max_pause_s: float) -> np.ndarray:
+    """
+    Shorten every silent stretch longer than `max_pause_s` to that length,
+    keeping its start and end so the speech on either side is untouched.
+    """
+    frames = len(audio) // PAUSE_FRAME
+    if not frames:
+        return audio
+    rms = np.sqrt(np.mean(audio[:frames * PAUSE_FRAME].reshape(frames, PAUSE_FRAME) ** 2, axis=1))
+    quiet = np.concatenate([[False], rms < PAUSE_THRESHOLD, [False]])
+    edges = np.flatnonzero(np.diff(quiet.astype(np.int8)))
+    keep = np.ones(len(audio), dtype=bool)
+    max_frames = int(max_pause_s * SAMPLE_RATE / PAUSE_FRAME)
+    for start, end in zip(edges[::2], edges[1::2]):
+        if end - start > max_frames:
+            head = start + max_frames // 2
+            tail = end - (max_frames - max_frames // 2)
+            keep[head * PAUSE_FRAME:tail * PAUSE_FRAME] = False
+    return audio[keep]
+
+def noise_gate(audio: np.ndarray, threshold_db: float, attack_s: float, release_s: float) -> np.ndarray:
+    """
+    Attenuate stretches whose level is below `threshold_db`. Unlike
+    compress_pauses no samples are removed, so timing is preserved; the gain
+    follows the per-frame level with separate attack and release times.
+    """
+    frames = -(-len(audio) // PAUSE_FRAME)
+    if not frames:
+        return audio
+    padded = np.pad(audio, (0, frames * PAUSE_FRAME - len(audio)))
+    rms = np.sqrt(np.mean(padded.reshape(frames, PAUSE_FRAME) ** 2, axis=1))
+    open_ = rms >= 10 ** (threshold_db / 20)
+    frame_s = PAUSE_FRAME / SAMPLE_RATE
+    attack, release = np.exp(-frame_s / attack_s), np.exp(-frame_s / release_s)
+    gain = np.empty(frames, dtype=np.float32)
+    level = 1.0 if open_[0] else 0.0
+    for i, target in enumerate(open_):
+        coefficient = attack if target else release
+        level = coefficient * level + (1 - coefficient) * float(target)
+        gain[i] = level
+    centres = (np.arange(frames) + 0.5) * PAUSE_FRAME
+    return (audio * np.interp(np.arange(len(audio)), centres, gain)).astype(np.float32)
+
+def k_weighting(freqs: np.ndarray, sample_rate: int = SAMPLE_RATE) -> np.ndarray:
+    """
+    Magnitude response of the ITU-R BS.1770 K-weighting filter (a +4 dB high
+    shelf followed by a ~38 Hz high-pass) at `freqs`, designed for `sample_rate`.
+    """
+    z = np.exp(-2j * np.pi * freqs / sample_rate)
+
+    def biquad(b, a):
+        return (b[0] + b[1] * z + b[2] * z ** 2) / (a[0] + a[1] * z + a[2] * z ** 2)
+
+    # Bilinear-transformed analog prototypes, which reproduce the
+    # standard's 48 kHz coefficients exactly (as libebur128 does)
+    K, Q = np.tan(np.pi * 1681.974450955533 / sample_rate), 0.7071752369554196
+    Vh = 10 ** (3.999843853973347 / 20)
+    Vb = Vh ** 0.4996667741545416
+    shelf = biquad((Vh + Vb * K / Q + K * K, 2 * (K * K - Vh), Vh - Vb * K / Q + K * K),
+                   (1 + K / Q + K * K, 2 * (K * K - 1), 1 - K / Q + K * K))
+    K, Q = np.tan(np.pi * 38.13547087602444 / sample_rate), 0.5003270373238773
+    a0 = 1 + K / Q + K * K
+    highpass = biquad((a0, -2 * a0, a0), (a0, 2 * (K * K - 1), 1 - K / Q + K * K))
+    return np.abs(shelf * highpass)
+
+def integrated_loudness(audio: np.ndarray) -> float:
+    """
+    BS.1770 integrated loudness in LUFS: K-weighted mean square over 400 ms
+    blocks (75% overlap), gated at -70 LUFS and then 10 LU below the
+    ungated level. The weighting is applied in the frequency domain, which
+    leaves the block energies (all that is measured) the same as filtering.
+    Returns -inf for audio that is silent or shorter than one block.
+    """
+    block, step = int(0.4 * SAMPLE_RATE), int(0.1 * SAMPLE_RATE)
+    if len(audio) < block:
+        return float("-inf")
+    weighted = np.fft.irfft(np.fft.rfft(audio) * k_weighting(np.fft.rfftfreq(len(audio), 1 / SAMPLE_RATE)), len(audio))
+    starts = np.arange(0, len(audio) - block + 1, step)
+    power = np.cumsum(np.concatenate([[0.0], weighted ** 2]))
+    z = (power[starts + block] - power[starts]) / block
+
+    def loudness(energies: np.ndarray) -> float:
+        return -0.691 + 10 * np.log10(np.mean(energies)) if len(energies) and np.mean(energies) > 0 else float("-inf")
+
+    z = z[-0.691 + 10 * np.log10(np.maximum(z, 1e-12)) > -70]
+    if not len(z):
+        return float("-inf")
+    return loudness(z[-0.691 + 10 * np.log10(z) > loudness(z) - 10])
+
+def normalize_loudness(audio: np.ndarray, target_lufs: float) -> np.ndarray:
+    """Apply the gain that brings the integrated loudness to `target_lufs` (silence is left alone)."""
+    measured = integrated_loudness(audio)
+    if not np.isfinite(measured):
+        return audio
+    logger.debug("Loudness %.1f LUFS, normalizing to %.1f LUFS", measured, target_lufs)
+    return (audio * 10 ** ((target_lufs - measured) / 20)).astype(np.float32)
+
+def trim_silence(audio: np.ndarray, threshold: float) -> np.ndarray:
+    """
+    Drop the samples before the first and after the last one reaching
+    `threshold` (linear amplitude), keeping TRIM_MARGIN_MS either side so soft
+    onsets and releases survive. Audio that never reaches it is left as is.
+    """
+    loud = np.flatnonzero(np.abs(audio) >= threshold)
+    if not len(loud):
+        return audio
+    margin = int(TRIM_MARGIN_MS * SAMPLE_RATE / 1000)
+    return audio[max(loud[0] - margin, 0):loud[-1] + 1 + margin]
+
+def apply_effects(audio: np.ndarray, req: SynthesisRequest) -> np.ndarray:
+    """Run the optional output effects configured on the request."""
+    if QUALITY_LEVELS[req.quality_level]["vibrato"]:
+        audio = apply_vibrato(audio, req.vibrato_rate, req.vibrato_depth)
+    if req.trim_silence:
+        audio = trim_silence(audio, 10 ** (req.silence_threshold_db / 20))
+    if req.noise_gate_db is not None:
+        audio = noise_gate(audio, req.noise_gate_db, req.gate_attack_ms / 1000, req.gate_release_ms / 1000)
+    if req.max_pause_ms is not None:
+        audio = compress_pauses(audio, req.max_pause_ms / 1000)
+    if req.pre_emphasis:
+        audio = pre_emphasis(audio, req.pre_emphasis)
+    if req.remove_dc:
+        audio = remove_dc(audio)
+    if req.normalize_loudness:
+        audio = normalize_loudness(audio, req.target_lufs)
+    if req.gain_db:
+        audio = (audio * 10 ** (req.gain_db / 20)).astype(np.float32)
+    if req.soft_limit:
+        audio = soft_limit(audio)
+    return audio
+
+def trim_to_window(audio: np.ndarray, start_s: Optional[float], end_s: Optional[float]) -> np.ndarray:
+    """Return the samples between start_s and end_s (either may be open)."""
+    start = int(round(start_s * SAMPLE_RATE)) if start_s is not None else 0
+    end = int(round(end_s * SAMPLE_RATE)) if end_s is not None else len(audio)
+    return audio[start:end]
+
+def measure_clipping(audio: np.ndarray) -> QualityReport:
+    """Count the samples the output encoders will have to clamp."""
+    peak = float(np.max(np.abs(audio))) if len(audio) else 0.0
+    report = QualityReport(clipped_samples=int(np.count_nonzero(np.abs(audio) > 1.0)), peak=peak)
+    if report.clipped_samples:
+        logger.warning("Output clipped: %d samples beyond full scale (peak %.3f)", report.clipped_samples, peak)
+    return report
+
+class Metrics:
+    """Process-wide counters, rendered in the Prometheus text exposition format."""
+
+    def __init__(self):
+        self.lock = threading.Lock()
+        self.requests = 0
+        self.syntheses = 0
+        self.samples = 0
+        self.failures: Dict[str, int] = {}
+        self.buckets = [0] * len(LATENCY_BUCKETS)
+        self.latency_sum = 0.0
+        self.latency_count = 0
+
+    def request(self):
+        with self.lock:
+            self.requests += 1
+
+    def generated(self, samples: int):
+        with self.lock:
+            self.samples += samples
+
+    def synthesis(self, seconds: float):
+        with self.lock:
+            self.syntheses += 1
+            self.latency_sum += seconds
+            self.latency_count += 1
+            for i, bound in enumerate(LATENCY_BUCKETS):
+                if seconds <= bound:
+                    self.buckets[i] += 1
+
+    def failure(self, error: StarletteHTTPException | Exception):
+        code = getattr(error, "error_code", None)
+        if code is None:
+            code = http_error_code(error) if isinstance(error, StarletteHTTPException) else "SYNTHESIS_FAILED"
+        with self.lock:
+            self.failures[code] = self.failures.get(code, 0) + 1
+
+    def exposition(self) -> str:
+        with self.lock:
+            lines = [
+                "# HELP kokoro_requests_total HTTP requests received.",
+                "# TYPE kokoro_requests_total counter",
+                f"kokoro_requests_total {self.requests}",
+                "# HELP kokoro_syntheses_total Successful syntheses.",
+                "# TYPE kokoro_syntheses_total counter",
+                f"kokoro_syntheses_total {self.syntheses}",
+                "# HELP kokoro_failures_total Failed requests by error code.",
+                "# TYPE kokoro_failures_total counter",
+                *(f'kokoro_failures_total{{error_code="{code}"}} {count}'
+                  for code, count in sorted(self.failures.items())),
+                "# HELP kokoro_samples_generated_total Audio samples generated by the model.",
+                "# TYPE kokoro_samples_generated_total counter",
+                f"kokoro_samples_generated_total {self.samples}",
+                "# HELP kokoro_synthesis_seconds Time to render a synthesis request.",
+                "# TYPE kokoro_synthesis_seconds histogram",
+                *(f'kokoro_synthesis_seconds_bucket{{le="{bound}"}} {count}'
+                  for bound, count in zip(LATENCY_BUCKETS, self.buckets)),
+                f'kokoro_synthesis_seconds_bucket{{le="+Inf"}} {self.latency_count}',
+                f"kokoro_synthesis_seconds_sum {self.latency_sum}",
+                f"kokoro_synthesis_seconds_count {self.latency_count}",
+            ]
+        return "\n".join(lines) + "\n"
+
+metrics = Metrics()
+
+@dataclass
+class Rendered:
+    audio: np.ndarray
+    normalizations: List[Normalization] = field(default_factory=list)
+    quality: Optional[QualityReport] = None
+    format: str = "wav"               # Output format, with "auto" resolved
+    sample_rate: int = SAMPLE_RATE
+    cache_hit: bool = False
+    voice: Optional[str] = None       # The voice used, with the request's default resolved
+
+class AudioCache:
+    """LRU cache of rendered requests, bounded by the total size of their audio."""
+
+    def __init__(self, max_bytes: int):
+        self.max_bytes = max_bytes
+        self.size = 0
+        self.entries: OrderedDict[str, Rendered] = OrderedDict()
+        self.lock = threading.Lock()
+
+    def get(self, key: str) -> Optional[Rendered]:
+        with self.lock:
+            if key in self.entries:
+                self.entries.move_to_end(key)
+                return self.entries[key]
+        return None
+
+    def put(self, key: str, rendered: Rendered):
+        if rendered.audio.nbytes > self.max_bytes:
+            return
+        with self.lock:
+            if key in self.entries:
+                self.size -= self.entries.pop(key).audio.nbytes
+            self.entries[key] = rendered
+            self.size += rendered.audio.nbytes
+            while self.size > self.max_bytes:
+                _, evicted = self.entries.popitem(last=False)
+                self.size -= evicted.audio.nbytes
+
+    def clear(self) -> int:
+        with self.lock:
+            count = len(self.entries)
+            self.entries.clear()
+            self.size = 0
+        return count
+
+audio_cache = AudioCache(CACHE_MAX_BYTES)
+
+# Request fields that only affect encoding or the response body, not the audio
+ENCODING_FIELDS = {"bit_depth", "bitrate", "id3_tags", "explain", "quality", "base64url"}
+
+def cache_key(req: SynthesisRequest, voice: str) -> str:
+    fields = req.model_dump(exclude=ENCODING_FIELDS)
+    fields["voice"] = voice
+    return hashlib.sha256(json.dumps(fields, sort_keys=True).encode()).hexdigest()
+
+def request_blend(req: SynthesisRequest) -> Optional[tuple[str, float]]:
+    return (req.voice2, req.blend) if req.voice2 else None
+
+def output_format(req: SynthesisRequest, duration: float) -> str:
+    """Resolve format "auto" by clip length: WAV for short clips (no encode cost), compressed for long ones."""
+    if req.format != "auto":
+        return req.format
+    return "wav" if duration <= req.auto_format_threshold_s else req.auto_compressed_format
+
+def render(req: SynthesisRequest) -> Rendered:
+    """
+    Full synthesis path shared by the endpoints: validation, normalization,
+    synthesis, output effects and the requested time window. Repeated
+    requests are answered from audio_cache.
+    """
+    started = time.perf_counter()
+    text = req.text.strip()
+    voice = req.voice or default_voice(req.language)
+    # Unseeded random variation must differ between identical requests, so it is never cached
+    key = cache_key(req, voice) if not req.variation or req.seed is not None else None
+    cached = audio_cache.get(key) if key else None
+    if cached:
+        metrics.synthesis(time.perf_counter() - started)
+        return replace(cached, cache_hit=True)
+    if not text and not req.phonemes:
+        raise APIError(400, "EMPTY_TEXT", "`text` or `phonemes` required")
+    check_text_length(len(req.phonemes or text))
+    check_voice(voice)
+    if req.voice2:
+        check_voice(req.voice2)
+    check_request_format(req)
+    check_sample_rate(req.sample_rate)
+    if req.end_s is not None and req.end_s <= (req.start_s or 0.0):
+        raise APIError(400, "INVALID_WINDOW", "`start_s` must be before `end_s`")
+
+    ssml = None
+    if req.phonemes:
+        text, normalizations = ipa_to_phonemes(req.phonemes), []
+    elif text.startswith("<speak"):
+        ssml, normalizations = ssml_runs(text, voice, req)
+    else:
+        text, normalizations = prepare_text(text, voice, req)
+    # Unseeded passes draw fresh randomness and leave the vocoder unseeded, so they share the model
+    speed, pitch = apply_variation(req.speed, req.variation, random.Random(req.seed))
+    pitch *= req.pitch
+    noise = np.random.default_rng(req.seed)
+    options = dict(word_pause_s=req.inter_word_pause_ms / 1000, consonant_boost=req.consonant_boost,
+                   phonemes=bool(req.phonemes), blend=request_blend(req), seed=req.seed, language=req.language,
+                   quality_level=req.quality_level)
+
+    def speak(part: str, end_s: Optional[float] = None) -> np.ndarray:
+        if req.parentheticals and "(" in part and not req.phonemes:
+            runs = split_parentheticals(part, req.parenthetical_pause_ms)
+            return render_runs(runs, voice, speed=speed, pitch=pitch, **options)
+        return synthesize(part, voice, speed=speed, pitch=pitch, end_s=end_s, **options)
+
+    if ssml is not None:
+        audio = render_runs(ssml, voice, speed=speed, pitch=pitch, **options)
+    elif req.breaths:
+        pieces = []
+        for sentence in split_sentences(text):
+            if pieces:
+                pieces.append(breath(req.breath_db, noise))
+            pieces.append(speak(sentence))
+        audio = np.concatenate(pieces) if pieces else np.zeros(0, dtype=np.float32)
+    else:
+        # Generation may stop at end_s unless effects shorten the audio before the window is cut
+        shortened = req.trim_silence or req.max_pause_ms is not None
+        audio = speak(text, None if shortened else req.end_s)
+    if not len(audio):
+        raise APIError(500, "SYNTHESIS_FAILED", "No audio generated")
+    audio = trim_to_window(apply_effects(audio, req), req.start_s, req.end_s)
+    if not len(audio):
+        raise APIError(400, "INVALID_WINDOW", "Requested window is past the end of the audio")
+    rate = TELEPHONY_SAMPLE_RATE if req.format in ("mulaw", "alaw") else req.sample_rate or SAMPLE_RATE
+    output = to_channels(resample_for(req.quality_level)(audio, SAMPLE_RATE, rate), req.channels, req.pan)
+    rendered = Rendered(output, normalizations, measure_clipping(audio),
+                        output_format(req, len(audio) / SAMPLE_RATE), rate, voice=voice)
+    if key:
+        audio_cache.put(key, rendered)
+    metrics.synthesis(time.perf_counter() - started)
+    return rendered
+
+def emphasize(text: str, level: int = 1) -> str:
+    """Raise (or with a negative level, lower) the stress of every word using misaki's [word](+1) markup."""
+    return re.sub(r"[^\W\d_]+(?:'[^\W\d_]+)?", lambda m: f"[{m.group(0)}]({level:+d})", text)
+
+async def render_until_disconnect(req: SynthesisRequest, request: Request) -> Rendered:
+    """
+    render() in a worker thread while watching the connection, so that a
+    client hanging up stops generation after the current segment instead of
+    the whole text being synthesized for nobody.
+    """
+    return await run_until_disconnect(request, render, req)
+
+async def run_until_disconnect(request: Request, work: Callable, *args):
+    """work(*args) in a worker thread, cancelled like render_until_disconnect."""
+    cancel = threading.Event()
+
+    def run():
+        render_state.cancel = cancel
+        try:
+            return work(*args)
+        finally:
+            render_state.cancel = None
+
+    task = asyncio.ensure_future(run_in_threadpool(run))
+    while not task.done():
+        await asyncio.wait([task], timeout=0.25)
+        if not task.done() and await request.is_disconnected():
+            cancel.set()
+    return task.result()
+
+def render_runs(runs: List[RichRun], voice: str, speed: float = 1.0, pitch: float = 1.0,
+                **options) -> np.ndarray:
+    """
+    Synthesize already-normalized runs one after another, applying each run's
+    emphasis, pitch and trailing pause. `options` go through to synthesize().
+    """
+    is_english = (language_entry(voice, options.get("language")) or {}).get("normalizer") == "en"
+    pieces = []
+    for run in runs:
+        text = run.text.strip()
+        if text:
+            level = int(run.emphasis) - int(run.deemphasis)
+            if level and is_english:
+                text = emphasize(text, level)
+            audio = synthesize(text, voice, speed=speed * run.speed, pitch=pitch * run.pitch, **options)
+            if level:
+                audio = audio * (EMPHASIS_GAIN if level > 0 else DEEMPHASIS_GAIN)
+            pieces.append(audio)
+        pieces.append(silence(run.break_ms))
+    crossfade = QUALITY_LEVELS[options.get("quality_level", "high")]["crossfade"]
+    return crossfade_join(pieces, CROSSFADE_SAMPLES if crossfade else 0)
+
+def split_parentheticals(text: str, pause_ms: float) -> List[RichRun]:
+    """Turn "a (b) c" into runs: "a", pause, de-emphasized "b", pause, "c"."""
+    runs = []
+    position = 0
+    for match in re.finditer(r"\(([^()]*)\)", text):
+        runs.append(RichRun(text=text[position:match.start()], break_ms=pause_ms))
+        runs.append(RichRun(text=match.group(1), deemphasis=True, break_ms=pause_ms))
+        position = match.end()
+    runs.append(RichRun(text=text[position:]))
+    return runs
+
+def split_sentences(text: str) -> List[str]:
+    """Split after sentence-final punctuation (and at line breaks), dropping empty pieces."""
+    return [s.strip() for s in re.split(r"(?<=[.!?…])\s+|\n+", text) if s.strip()]
+
+def breath(level_db: float, rng: Optional[np.random.Generator] = None) -> np.ndarray:
+    """
+    A synthetic inhalation: white noise band-passed to BREATH_BAND_HZ, with
+    a fast attack and slow decay envelope peaking at `level_db`, padded by
+    BREATH_GAP_MS of silence on both sides.
+    """
+    length = int(BREATH_MS * SAMPLE_RATE / 1000)
+    spectrum = np.fft.rfft((rng or np.random.default_rng()).standard_normal(length))
+    freqs = np.fft.rfftfreq(length, 1 / SAMPLE_RATE)
+    spectrum[(freqs < BREATH_BAND_HZ[0]) | (freqs > BREATH_BAND_HZ[1])] = 0
+    noise = np.fft.irfft(spectrum, length)
+    rise = length // 4
+    envelope = np.concatenate([np.sin(np.linspace(0, np.pi / 2, rise)) ** 2,
+                               np.cos(np.linspace(0, np.pi / 2, length - rise)) ** 2])
+    shaped = noise * envelope
+    shaped *= 10 ** (level_db / 20) / (np.max(np.abs(shaped)) or 1.0)
+    gap = silence(BREATH_GAP_MS)
+    return np.concatenate([gap, shaped.astype(np.float32), gap])
+
+def ssml_runs(markup: str, voice: str, req: SynthesisRequest) -> tuple[List[RichRun], List[Normalization]]:
+    """Parse an SSML request text into normalized runs (see ssml.py for the supported subset)."""
+    try:
+        parsed = parse_ssml(markup)
+    except SsmlError as e:
+        raise APIError(400, "INVALID_SSML", str(e))
+    runs, normalizations = [], []
+    for run in parsed:
+        text, applied = prepare_text(run.text.strip(), voice, req)
+        normalizations += applied
+        runs.append(RichRun(text=text, emphasis=run.level > 0, deemphasis=run.level < 0,
+                            speed=min(max(run.rate, 0.25), 4.0), break_ms=run.break_ms))
+    return runs, normalizations
+
+def render_rich(doc: RichDocument) -> np.ndarray:
+    """Render each run with its paragraph's voice and its own styling, in order."""
+    pieces = []
+    for paragraph in doc.paragraphs:
+        voice = paragraph.voice or settings.voice
+        runs = [run.model_copy(update={"text": prepare_text(run.text.strip(), voice)[0]})
+                for run in paragraph.runs]
+        pieces.append(render_runs(runs, voice, speed=doc.speed))
+        pieces.append(silence(doc.paragraph_break_ms))
+    return np.concatenate(pieces[:-1]) if len(pieces) > 1 else np.zeros(0, dtype=np.float32)
+
+def audio_to_wav(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = SAMPLE_RATE) -> bytes:
+    """
+    Encode float audio in [-1, 1] as a 16- or 24-bit WAV file. `audio` is either
+    mono (samples,) or interleaved (frames, channels); multichannel files use
+    WAVE_FORMAT_EXTENSIBLE so players and DAWs can route channels correctly.
+    """
+    channels = 1 if audio.ndim == 1 else audio.shape[1]
+    pcm = to_pcm(audio, bit_depth)
+    return wav_header(channels, bit_depth, len(pcm), sample_rate) + pcm
+
+def to_pcm(audio: np.ndarray, bit_depth: int = 16) -> bytes:
+    """Little-endian signed PCM samples, clamped to full scale."""
+    clipped = np.clip(audio, -1.0, 1.0)
+    if bit_depth == 24:
+        ints = np.round(clipped * 8388607).astype("<i4")
+        return ints.view(np.uint8).reshape(-1, 4)[:, :3].tobytes()
+    return (clipped * 32767).astype("<i2").tobytes()
+
+def wav_header(channels: int, bit_depth: int, data_size: int, sample_rate: int = SAMPLE_RATE,
+               format_tag: int = WAVE_FORMAT_PCM) -> bytes:
+    """
+    RIFF header for `data_size` bytes of samples; 0xFFFFFFFF marks an unknown
+    (streamed) length. Companded (non-PCM) formats have a plain header even
+    in stereo, since WAVE_FORMAT_EXTENSIBLE is only needed for PCM layouts.
+    """
+    block_align = channels * bit_depth // 8
+    tag = 0xFFFE if channels > 1 and format_tag == WAVE_FORMAT_PCM else format_tag
+    fmt = struct.pack("<HHIIHH", tag, channels, sample_rate,
+                      sample_rate * block_align, block_align, bit_depth)
+    if format_tag != WAVE_FORMAT_PCM:
+        fmt += struct.pack("<H", 0)  # no extra format bytes
+    elif channels > 1:
+        fmt += struct.pack("<HHI", 22, bit_depth, CHANNEL_MASKS.get(channels, 0)) + PCM_SUBFORMAT_GUID
+    riff_size = 0xFFFFFFFF if data_size == 0xFFFFFFFF else 4 + 8 + len(fmt) + 8 + data_size
+    return b"".join([
+        b"RIFF", struct.pack("<I", riff_size), b"WAVE",
+        b"fmt ", struct.pack("<I", len(fmt)), fmt,
+        b"data", struct.pack("<I", data_size),
+    ])
+
+def encode_mulaw(audio: np.ndarray) -> bytes:
+    """G.711 μ-law: each sample's 14-bit magnitude as a 3-bit segment and 4-bit step, bits inverted."""
+    pcm = (np.clip(audio, -1.0, 1.0) * 32767).astype(np.int32).ravel() >> 2
+    magnitude = np.minimum(np.abs(pcm), 8158) + 33  # biased so segments start at powers of two
+    segment = np.floor(np.log2(magnitude)).astype(np.int32) - 5
+    step = (magnitude >> (segment + 1)) & 0x0F
+    return (~(((pcm < 0).astype(np.int32) << 7) | (segment << 4) | step) & 0xFF).astype(np.uint8).tobytes()
+
+def encode_alaw(audio: np.ndarray) -> bytes:
+    """G.711 A-law: each sample's 13-bit magnitude as a 3-bit segment and 4-bit step, even bits inverted."""
+    pcm = (np.clip(audio, -1.0, 1.0) * 32767).astype(np.int32).ravel() >> 3
+    negative = pcm < 0
+    magnitude = np.minimum(np.where(negative, -pcm - 1, pcm), 0xFFF)
+    segment = np.maximum(np.floor(np.log2(np.maximum(magnitude, 1))).astype(np.int32) - 4, 0)
+    step = np.where(segment < 2, magnitude >> 1, magnitude >> segment) & 0x0F
+    return (((segment << 4) | step) ^ np.where(negative, 0x55, 0xD5)).astype(np.uint8).tobytes()
+
+def audio_to_mulaw(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = TELEPHONY_SAMPLE_RATE) -> bytes:
+    """Encode float audio as an 8-bit μ-law WAV (bit depth does not apply)."""
+    channels = 1 if audio.ndim == 1 else audio.shape[1]
+    data = encode_mulaw(audio)
+    return wav_header(channels, 8, len(data), sample_rate, WAVE_FORMAT_MULAW) + data
+
+def audio_to_alaw(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = TELEPHONY_SAMPLE_RATE) -> bytes:
+    """Encode float audio as an 8-bit A-law WAV (bit depth does not apply)."""
+    channels = 1 if audio.ndim == 1 else audio.shape[1]
+    data = encode_alaw(audio)
+    return wav_header(channels, 8, len(data), sample_rate, WAVE_FORMAT_ALAW) + data
+
+def audio_to_flac(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = SAMPLE_RATE) -> bytes:
+    """Encode float audio as lossless FLAC at 16 or 24 bits per sample."""
+    buf = io.BytesIO()
+    sf.write(buf, np.clip(audio, -1.0, 1.0), sample_rate, format="FLAC",
+             subtype="PCM_24" if bit_depth == 24 else "PCM_16")
+    return buf.getvalue()
+
+def audio_to_mp3(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = SAMPLE_RATE,
+                 bitrate: int = 128) -> bytes:
+    """
+    Encode float audio as constant-bitrate MP3 (bit depth does not apply).
+    libsndfile takes the bitrate as a compression level, where 0 is the
+    highest bitrate the sample rate allows (MP3_BITRATE_RANGES) and 1 the lowest.
+    """
+    low, high = next(r for floor, r in MP3_BITRATE_RANGES.items() if sample_rate >= floor)
+    level = (high - min(max(bitrate, low), high)) / (high - low)
+    buf = io.BytesIO()
+    sf.write(buf, np.clip(audio, -1.0, 1.0), sample_rate, format="MP3", subtype="MPEG_LAYER_III",
+             bitrate_mode="CONSTANT", compression_level=level)
+    return buf.getvalue()
+
+def audio_to_ogg(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = SAMPLE_RATE) -> bytes:
+    """Encode float audio as Ogg Vorbis (bit depth does not apply)."""
+    # libvorbis writes a container without any audio page for zero samples
+    if not len(audio):
+        raise APIError(400, "EMPTY_AUDIO", "No audio to encode as OGG")
+    buf = io.BytesIO()
+    sf.write(buf, np.clip(audio, -1.0, 1.0), sample_rate, format="OGG", subtype="VORBIS")
+    return buf.getvalue()
+
+def audio_to_opus(audio: np.ndarray, bit_depth: int = 16, sample_rate: int = SAMPLE_RATE) -> bytes:
+    """Encode float audio as Opus in an Ogg container (bit depth does not apply)."""
+    if not len(audio):
+        raise APIError(400, "EMPTY_AUDIO", "No audio to encode as Opus")
+    buf = io.BytesIO()
+    sf.write(buf, np.clip(audio, -1.0, 1.0), sample_rate, format="OGG", subtype="OPUS")
+    return buf.getvalue()
+
+def id3_tag(title: str, comment: str) -> bytes:
+    """Build an ID3v2.3 tag with UTF-16 TIT2 (title) and COMM (comment) frames."""
+    def frame(frame_id: bytes, data: bytes) -> bytes:
+        return frame_id + struct.pack(">IH", len(data), 0) + data
+
+    frames = frame(b"TIT2", b"\x01" + title.encode("utf-16"))
+    frames += frame(b"COMM", b"\x01eng" + "".encode("utf-16") + b"\x00\x00" + comment.encode("utf-16"))
+    size = len(frames)
+    syncsafe = bytes([(size >> 21) & 0x7F, (size >> 14) & 0x7F, (size >> 7) & 0x7F, size & 0x7F])
+    return b"ID3\x03\x00\x00" + syncsafe + frames
+
+# Output formats: encoder, media type, and the libsndfile format (optionally
+# "FORMAT/SUBTYPE") it needs (None for the built-in writers)
+ENCODERS = {
+    "wav": (audio_to_wav, "audio/wav", None),
+    "flac": (audio_to_flac, "audio/flac", "FLAC"),
+    "mp3": (audio_to_mp3, "audio/mpeg", "MP3"),
+    "ogg": (audio_to_ogg, "audio/ogg", "OGG"),
+    "opus": (audio_to_opus, "audio/opus", "OGG/OPUS"),
+    "mulaw": (audio_to_mulaw, "audio/wav", None),
+    "alaw": (audio_to_alaw, "audio/wav", None),
+}
+# Formats whose files are named for their container rather than the format
+FILE_EXTENSIONS = {"mulaw": "wav", "alaw": "wav"}
+
+def supported_formats() -> List[str]:
+    """Output formats whose encoder is usable with the installed libsndfile."""
+    def usable(needs: Optional[str]) -> bool:
+        if needs is None:
+            return True
+        fmt, _, subtype = needs.partition("/")
+        return fmt in sf.available_formats() and (not subtype or subtype in sf.available_subtypes(fmt))
+    return [name for name, (_, _, needs) in ENCODERS.items() if usable(needs)]
+
+def check_format(fmt: str):
+    if fmt not in supported_formats():
+        raise APIError(400, "UNSUPPORTED_FORMAT",
+                       f"Format '{fmt}' not supported by this server. Available: {supported_formats()}")
+
+def check_request_format(req: SynthesisRequest):
+    fmt = req.auto_compressed_format if req.format == "auto" else req.format
+    rate = req.sample_rate or SAMPLE_RATE
+    if fmt == "opus" and rate not in OPUS_SAMPLE_RATES:
+        raise APIError(400, "UNSUPPORTED_SAMPLE_RATE",
+                       f"Opus does not support {rate} Hz. Available: {list(OPUS_SAMPLE_RATES)}")
+    check_format(fmt)
+
+def to_textgrid(duration: float, tiers: Dict[str, List[tuple[float, float, str]]]) -> str:
+    """
+    Format interval tiers as a Praat TextGrid (long text format). Gaps between
+    intervals are filled with empty intervals, as Praat requires each tier to
+    cover the whole file.
+    """
+    def quote(label: str) -> str:
+        return '"' + label.replace('"', '""') + '"'
+
+    lines = ['File type = "ooTextFile"', 'Object class = "TextGrid"', "",
+             "xmin = 0", f"xmax = {duration}", "tiers? <exists>", f"size = {len(tiers)}", "item []:"]
+    for index, (name, intervals) in enumerate(tiers.items(), start=1):
+        filled, cursor = [], 0.0
+        for start, end, label in intervals:
+            start, end = max(start, cursor), min(end, duration)
+            if end <= start:
+                continue
+            if start > cursor:
+                filled.append((cursor, start, ""))
+            filled.append((start, end, label))
+            cursor = end
+        if cursor < duration or not filled:
+            filled.append((cursor, duration, ""))
+        lines += [f"    item [{index}]:", '        class = "IntervalTier"', f"        name = {quote(name)}",
+                  "        xmin = 0", f"        xmax = {duration}", f"        intervals: size = {len(filled)}"]
+        for i, (start, end, label) in enumerate(filled, start=1):
+            lines += [f"        intervals [{i}]:", f"            xmin = {start}",
+                      f"            xmax = {end}", f"            text = {quote(label)}"]
+    return "\n".join(lines) + "\n"
+
+def encode_audio(audio: np.ndarray, fmt: str = "wav", bit_depth: int = 16,
+                 sample_rate: int = SAMPLE_RATE) -> bytes:
+    encoder, _, _ = ENCODERS[fmt]
+    return encoder(audio, bit_depth, sample_rate)
+
+def encode_for_request(rendered: Rendered, req: SynthesisRequest) -> bytes:
+    """Encode in the rendered output format, adding ID3 tags to MP3 if asked for."""
+    if rendered.format == "mp3":
+        data = audio_to_mp3(rendered.audio, sample_rate=rendered.sample_rate, bitrate=req.bitrate)
+    else:
+        data = encode_audio(rendered.audio, rendered.format, req.bit_depth, rendered.sample_rate)
+    if rendered.format == "mp3" and req.id3_tags:
+        text = " ".join(req.text.split())
+        title = text if len(text) <= 60 else text[:59] + "…"
+        comment = f"voice={rendered.voice} speed={req.speed}"
+        data = id3_tag(title, comment) + data
+    return data
+
+def file_response(data: bytes, fmt: str = "wav", name: str = "output",
+                  headers: Optional[Dict[str, str]] = None) -> StreamingResponse:
+    return StreamingResponse(
+        io.BytesIO(data),
+        media_type=ENCODERS[fmt][1],
+        headers={"Content-Disposition": f"attachment; filename={name}.{FILE_EXTENSIONS.get(fmt, fmt)}",
+                 **(headers or {})}
+    )
+
+def request_etag(req: SynthesisRequest) -> Optional[str]:
+    """
+    Strong ETag of the file a request produces, from every field (encoding
+    included), the voice it resolves to and the server state behind it: the
+    model generation and the size and mtime of each voice file, so replacing
+    a pack or reloading the model invalidates it. Only seeded requests have
+    one: without a seed the vocoder noise, and any variation or breaths,
+    differ from one pass to the next.
+    """
+    if req.seed is None:
+        return None
+    voice = req.voice or default_voice(req.language)
+    files = voice_files()
+    def file_state(name: Optional[str]) -> Optional[list]:
+        if name is None or name not in files:
+            return None
+        stat = files[name].stat()
+        return [stat.st_size, stat.st_mtime_ns]
+    fields = {**req.model_dump(), "voice": voice, "model_generation": model_generation,
+              "voice_files": [file_state(voice), file_state(req.voice2)]}
+    return '"%s"' % hashlib.sha256(json.dumps(fields, sort_keys=True).encode()).hexdigest()[:32]
+
+def etag_matches(etag: str, if_none_match: Optional[str]) -> bool:
+    if not if_none_match:
+        return False
+    # Either representation of the file validates it (see compress_response)
+    tags = [tag.strip().removeprefix("W/").replace('-gzip"', '"') for tag in if_none_match.split(",")]
+    return "*" in tags or etag in tags
+
+def audio_response(audio: np.ndarray, fmt: str = "wav", bit_depth: int = 16,
+                   name: str = "output") -> StreamingResponse:
+    return file_response(encode_audio(audio, fmt, bit_depth), fmt, name)
+
+@dataclass
+class Job:
+    """A queued synthesis; the finished audio is kept gzip-compressed."""
+    id: str
+    format: str
+    status: str = "queued"
+    error: Optional[str] = None
+    error_code: Optional[str] = None
+    size_bytes: Optional[int] = None
+    data: Optional[bytes] = None
+    progress: float = 0.0
+    finished_at: Optional[float] = None  # time.monotonic() when it succeeded or failed
+
+    def describe(self) -> JobStatus:
+        return JobStatus(id=self.id, status=self.status, format=self.format, error=self.error,
+                         error_code=self.error_code, size_bytes=self.size_bytes, progress=self.progress,
+                         stored_bytes=len(self.data) if self.data is not None else None)
+
+jobs: Dict[str, Job] = {}
+
+def prune_jobs():
+    """Forget jobs that finished more than JOB_TTL_S ago, freeing their audio."""
+    cutoff = time.monotonic() - JOB_TTL_S
+    for job_id in [job.id for job in jobs.values() if job.finished_at is not None and job.finished_at < cutoff]:
+        del jobs[job_id]
+
+def run_job(job: Job, req: SynthesisRequest):
+    job.status = "running"
+    total, done = max(len(req.phonemes or req.text), 1), 0
+
+    def advance(chars: int):
+        nonlocal done
+        done += chars
+        job.progress = min(done / total, 0.99)  # normalization can lengthen the text
+
+    render_state.on_segment = advance
+    try:
+        rendered = render(req)
+        data = encode_for_request(rendered, req)
+    except HTTPException as e:
+        metrics.failure(e)
+        job.status, job.error, job.error_code = "failed", str(e.detail), getattr(e, "error_code", None)
+        job.finished_at = time.monotonic()
+        return
+    except Exception as e:
+        logger.exception("Job %s failed", job.id)
+        metrics.failure(e)
+        job.status, job.error, job.error_code = "failed", str(e), "SYNTHESIS_FAILED"
+        job.finished_at = time.monotonic()
+        return
+    finally:
+        render_state.on_segment = None
+    job.format = rendered.format
+    job.size_bytes = len(data)
+    job.data = gzip.compress(data)
+    job.progress = 1.0
+    job.status = "done"
+    job.finished_at = time.monotonic()
+    logger.info("Job %s done: %d bytes stored as %d", job.id, job.size_bytes, len(job.data))
+
+def synthesis_response(rendered: Rendered, req: SynthesisRequest) -> SynthesisResponse:
+    """The JSON body of /synthesize for a rendered request."""
+    data = encode_for_request(rendered, req)
+    if req.base64url:
+        audio_data = base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+    else:
+        audio_data = base64.b64encode(data).decode()
+    return SynthesisResponse(
+        audio_data=audio_data,
+        format=rendered.format,
+        sample_rate=rendered.sample_rate,
+        duration_seconds=len(rendered.audio) / rendered.sample_rate,
+        normalizations=rendered.normalizations if req.explain else None,
+        quality=rendered.quality if req.quality else None,
+        cache_hit=rendered.cache_hit,
+    )
+
+def stream_pcm(req: SynthesisRequest) -> Iterator[bytes]:
+    """
+    Validate a request and return a generator of its PCM, one chunk per
+    pipeline segment. Output effects, time windows and parentheticals need
+    the whole clip and do not apply.
+    """
+    text = req.text.strip()
+    voice = req.voice or default_voice(req.language)
+    if not text:
+        raise APIError(400, "EMPTY_TEXT", "`text` required")
+    check_text_length(len(text))
+    check_voice(voice)
+    language_entry(voice, req.language)  # an unknown language must fail before a stream starts too
+    if req.voice2:
+        blend_voices(voice, req.voice2, req.blend)  # incompatible packs must fail before a stream starts
+    check_sample_rate(req.sample_rate)
+    rate = req.sample_rate or SAMPLE_RATE
+    text, _ = prepare_text(text, voice, req)
+    speed, pitch = apply_variation(req.speed, req.variation, random.Random(req.seed))
+    pitch *= req.pitch
+
+    def chunks() -> Iterator[bytes]:
+        for _, audio in pipeline_segments(text, voice, speed, pitch, req.inter_word_pause_ms / 1000,
+                                          req.consonant_boost, blend=request_blend(req), seed=req.seed,
+                                          language=req.language):
+            audio = change_playback_rate(audio, pitch, QUALITY_LEVELS[req.quality_level]["sinc"])
+            audio = resample_for(req.quality_level)(audio, SAMPLE_RATE, rate)
+            yield to_pcm(to_channels(audio, req.channels, req.pan), req.bit_depth)
+
+    return chunks()
+
+@app.exception_handler(StarletteHTTPException)
+async def http_error(request: Request, exc: StarletteHTTPException):
+    """
+    Error body: `detail` and `error_code`, also for Starlette's own errors
+    (unknown route, wrong method), which get a code from HTTP_ERROR_CODES.
+    """
+    metrics.failure(exc)
+    body = {"detail": exc.detail, "error_code": http_error_code(exc)}
+    return JSONResponse(body, status_code=exc.status_code, headers=exc.headers)
+
+@app.exception_handler(RequestValidationError)
+async def invalid_request(request: Request, exc: RequestValidationError):
+    """
+    Malformed JSON or fields that fail validation: 400 INVALID_REQUEST, with
+    `detail` listing each problem (`loc` is the path to the field, `msg` why).
+    """
+    metrics.failure(APIError(400, "INVALID_REQUEST", "Invalid request"))
+    errors = [{"loc": e["loc"], "msg": e["msg"], "type": e["type"]} for e in exc.errors()]
+    return JSONResponse({"detail": jsonable_encoder(errors), "error_code": "INVALID_REQUEST"}, status_code=400)
+
+@app.exception_handler(SynthesisCancelled)
+async def synthesis_cancelled(request: Request, exc: SynthesisCancelled):
+    logger.info("Client disconnected, synthesis abandoned")
+    return Response(status_code=499)  # nobody is there to read it
+
+@app.exception_handler(Exception)
+async def unexpected_error(request: Request, exc: Exception):
+    logger.exception("Unhandled error")
+    metrics.failure(exc)
+    return JSONResponse({"detail": "Synthesis failed", "error_code": "SYNTHESIS_FAILED"}, status_code=500)
+
+class RateLimiter:
+    """
+    Token buckets per client IP: each holds up to a minute's allowance and
+    refills continuously, so a client may burst that many requests and then
+    continues at the steady rate.
+    """
+    MAX_CLIENTS = 10000  # buckets kept before full (idle) ones are dropped
+
+    def __init__(self, per_minute: int):
+        self.per_minute = per_minute
+        self.buckets: Dict[str, tuple[float, float]] = {}  # ip -> (tokens, last update)
+        self.lock = threading.Lock()
+
+    def acquire(self, client: str) -> float:
+        """Take a token for `client`; returns 0, or the seconds until one is available."""
+        now = time.monotonic()
+        refill = self.per_minute / 60
+        with self.lock:
+            tokens, updated = self.buckets.get(client, (self.per_minute, now))
+            tokens = min(self.per_minute, tokens + (now - updated) * refill)
+            if tokens < 1:
+                self.buckets[client] = (tokens, now)
+                return (1 - tokens) / refill
+            self.buckets[client] = (tokens - 1, now)
+            if len(self.buckets) > self.MAX_CLIENTS:
+                self.buckets = {ip: (t, u) for ip, (t, u) in self.buckets.items()
+                                if t + (now - u) * refill < self.per_minute}
+        return 0.0
+
+# Requests per minute per client IP (--rate-limit or KOKORO_RATE_LIMIT); 0 disables limiting
+RATE_LIMIT = int(os.environ.get("KOKORO_RATE_LIMIT", "0"))
+rate_limiter = RateLimiter(RATE_LIMIT)
+# Health and monitoring probes are never limited
+RATE_LIMIT_EXEMPT = {"/status", "/metrics"}
+
+@app.middleware("http")
+async def limit_rate(request: Request, call_next):
+    """Answer clients over their allowance with 429 and a Retry-After (seconds)."""
+    if rate_limiter.per_minute and request.url.path not in RATE_LIMIT_EXEMPT:
+        wait = rate_limiter.acquire(request.client.host if request.client else "-")
+        if wait:
+            error = APIError(429, "RATE_LIMITED", f"Rate limit of {rate_limiter.per_minute} requests per minute exceeded")
+            metrics.failure(error)
+            return JSONResponse({"detail": error.detail, "error_code": error.error_code}, status_code=429,
+                                headers={"Retry-After": str(math.ceil(wait))})
+    return await call_next(request)
+
+@app.middleware("http")
+async def correlate_request(request: Request, call_next):
+    """
+    Tag the request with a correlation ID (from X-Request-Id, or a new one) for
+    all log lines it produces, and echo it back in the response header.
+    """
+    request_id = request.headers.get("X-Request-Id") or uuid.uuid4().hex
+    token = request_id_var.set(request_id)
+    metrics.request()
+    try:
+        logger.info("%s %s", request.method, request.url.path)
+        response = await call_next(request)
+        logger.info("%s %s -> %d", request.method, request.url.path, response.status_code)
+    finally:
+        request_id_var.reset(token)
+    response.headers["X-Request-Id"] = request_id
+    return response
+
+# Response types worth gzipping: JSON (base64 audio) and PCM WAV. MP3, Ogg,
+# Opus and FLAC are compressed already; event streams must not be buffered.
+COMPRESSIBLE_TYPES = ("application/json", "audio/wav", "text/plain")
+GZIP_MIN_BYTES = 512
+
+@app.middleware("http")
+async def compress_response(request: Request, call_next):
+    """
+    Gzip compressible responses for clients sending Accept-Encoding: gzip.
+    Bodies are compressed chunk by chunk with a flush after each, so streamed
+    WAV still reaches the client segment by segment. An ETag gets a -gzip
+    suffix, as the compressed bytes are a representation of their own.
+    """
+    response = await call_next(request)
+    length = response.headers.get("content-length")
+    if ("gzip" not in request.headers.get("Accept-Encoding", "")
+            or "content-encoding" in response.headers
+            or not response.headers.get("content-type", "").startswith(COMPRESSIBLE_TYPES)
+            or length is not None and int(length) < GZIP_MIN_BYTES):
+        return response
+    compressor = zlib.compressobj(6, zlib.DEFLATED, 16 + zlib.MAX_WBITS)  # gzip container
+
+    async def body():
+        async for chunk in response.body_iterator:
+            yield compressor.compress(chunk) + compressor.flush(zlib.Z_SYNC_FLUSH)
+        yield compressor.flush()
+
+    headers = {k: v for k, v in response.headers.items() if k != "content-length"}
+    headers["content-encoding"] = "gzip"
+    headers["vary"] = "Accept-Encoding"
+    if "etag" in headers:
+        headers["etag"] = headers["etag"].removesuffix('"') + '-gzip"'
+    return StreamingResponse(body(), status_code=response.status_code, headers=headers)
+
+@app.get("/metrics")
+async def metrics_endpoint():
+    """Counters and the synthesis latency histogram, for Prometheus to scrape."""
+    return Response(metrics.exposition(), media_type="text/plain; version=0.0.4")
+
+@app.get("/status")
+async def status():
+    """
+    Report server health and capabilities, including the output formats the
+    installed encoders support.
+    """
+    return {
+        "status": "ok",
+        "sample_rate": SAMPLE_RATE,
+        "sample_rates": list(OUTPUT_SAMPLE_RATES),
+        "formats": supported_formats(),
+        "voices": len(get_available_voices()),
+    }
+
+@app.get("/config")
+async def get_config(keys_only: bool = False):
+    """The loaded model config.json, or with `keys_only` just its top-level keys (the vocab is large)."""
+    return sorted(model_config) if keys_only else model_config
+
+@app.get("/schema")
+async def schema():
+    """
+    JSON Schema of the synthesis request body, generated from SynthesisRequest
+    so it always matches what the endpoints accept. `format` is narrowed to
+    the encoders available on this server.
+    """
+    request_schema = SynthesisRequest.model_json_schema()
+    request_schema["properties"]["format"]["enum"] = supported_formats() + ["auto"]
+    auto_formats = request_schema["properties"]["auto_compressed_format"]["enum"]
+    request_schema["properties"]["auto_compressed_format"]["enum"] = [
+        fmt for fmt in supported_formats() if fmt in auto_formats]
+    return request_schema
 
 @app.get("/settings", response_model=SettingsOut)
 async def get_settings():
@@ -60,68 +2133,596 @@ async def update_settings(new: Settings):
     """
     Update synthesis settings: lang_code, voice. Validates voice is available.
     """
-    global settings, pipeline
+    global settings
     voices = get_available_voices()
     if new.voice not in voices:
-        raise HTTPException(status_code=400, detail=f"Voice '{new.voice}' not found. Available: {voices}")
+        raise APIError(400, "INVALID_VOICE", f"Voice '{new.voice}' not found. Available: {voices}")
     settings = new
-    pipeline = create_pipeline(settings.lang_code)
+    get_pipeline(settings.lang_code)
     return SettingsOut(
         lang_code=settings.lang_code,
         voice=settings.voice,
         available_voices=voices
     )
 
+@app.get("/voices/projection", response_model=List[VoicePoint])
+def voice_projection():
+    """
+    Project all voice embeddings onto their first two principal components, so
+    similar voices land near each other in a scatter plot. A plain function,
+    so loading every pack happens in a worker thread.
+    """
+    paths = [path for _, path in sorted(voice_files().items())]
+    if not paths:
+        return []
+    embeddings = np.stack([load_voice_embedding(p) for p in paths])
+    centered = embeddings - embeddings.mean(axis=0)
+    _, _, components = np.linalg.svd(centered, full_matrices=False)
+    coords = centered @ components[:2].T
+    if coords.shape[1] < 2:  # a single voice has only one (degenerate) component
+        coords = np.pad(coords, ((0, 0), (0, 2 - coords.shape[1])))
+    return [VoicePoint(name=p.stem, x=float(x), y=float(y)) for p, (x, y) in zip(paths, coords)]
+
+@app.get("/voices/{name}/profile", response_model=VoiceProfile)
+async def voice_profile(name: str, request: Request):
+    """
+    Describe a voice: language and gender from its name, embedding shape, and
+    a pitch range measured on a short synthesized sample (included as audio).
+    """
+    path = voice_files().get(Path(name).stem)
+    if path is None:
+        raise APIError(404, "VOICE_NOT_FOUND", f"Voice '{name}' not found. Available: {get_available_voices()}")
+    sample_text = TEST_SENTENCES[0]
+
+    def measure():
+        pack = torch.load(path, map_location="cpu", weights_only=True)
+        audio = synthesize(prepare_text(sample_text, path.stem)[0], path.stem)
+        return pack.shape, audio, estimate_pitch(audio)
+
+    shape, audio, pitch = await run_until_disconnect(request, measure)
+    return VoiceProfile(
+        name=path.stem,
+        **voice_info(path.stem),
+        embedding_shape=list(shape),
+        pitch=pitch,
+        sample_text=sample_text,
+        sample_audio=base64.b64encode(audio_to_wav(audio)).decode(),
+    )
+
+@app.get("/languages", response_model=List[LanguageInfo], response_model_exclude_none=True)
+async def list_languages():
+    """The languages texts may be written in, from the built-in table and LANGUAGES_PATH."""
+    return [LanguageInfo(code=entry["code"], name=entry.get("name", entry["code"]), lang_code=entry["lang_code"],
+                         default_voice=default_voices.get(entry["code"]) or default_voices.get(entry["code"].split("-")[0]))
+            for entry in languages.values() if "code" in entry]
+
+@app.get("/voices", response_model=List[VoiceInfo] | List[str])
+async def list_voices(format: Optional[Literal["names"]] = None):
+    """The installed voices with what their names tell; `?format=names` for just the names."""
+    names = sorted(Path(name).stem for name in get_available_voices())
+    if format == "names":
+        return names
+    return [VoiceInfo(name=name, **voice_info(name)) for name in names]
+
+@app.get("/voices/{name}", response_model=VoiceDetails)
+def voice_details(name: str):
+    """
+    Metadata for one voice without synthesizing anything: language and gender
+    from its name, and the shape and size of its embedding, to spot malformed
+    voice files. A plain function, so the pack loads in a worker thread.
+    """
+    path = voice_files().get(Path(name).stem)
+    if path is None:
+        raise APIError(404, "VOICE_NOT_FOUND", f"Voice '{name}' not found. Available: {get_available_voices()}")
+    pack = torch.load(path, map_location="cpu", weights_only=True)
+    well_formed = pack.dim() == 3 and tuple(pack.shape[1:]) == (1, STYLE_DIM)
+    style = pack.reshape(-1, pack.shape[-1]).mean(dim=0) if pack.dim() else pack.reshape(1)
+    half = style.shape[0] // 2
+    return VoiceDetails(
+        name=path.stem,
+        **voice_info(path.stem),
+        embedding_shape=list(pack.shape),
+        embedding_length=pack.numel(),
+        well_formed=well_formed,
+        timbre_norm=float(style[:half].norm()),
+        prosody_norm=float(style[half:].norm()),
+    )
+
+@app.post("/cache/clear")
+async def clear_cache():
+    """Drop every cached render."""
+    return {"cleared": audio_cache.clear()}
+
+@app.post("/warmup", response_model=WarmupResult)
+async def warmup(req: WarmupRequest, request: Request):
+    """
+    Render each phrase into the cache (loading its pipeline and voice on the
+    way), so the first real requests for them are answered without synthesis.
+    """
+    if req.options.variation and req.options.seed is None:
+        raise APIError(400, "INVALID_REQUEST", "Renders with unseeded variation are never cached; set a seed")
+    for phrase in req.phrases:
+        await render_until_disconnect(req.options.model_copy(update={"text": phrase}), request)
+    return WarmupResult(warmed=len(req.phrases), cache_bytes=audio_cache.size)
+
+@app.post("/reload")
+def reload():
+    """
+    Reload the core model and its config, the language table and default
+    voices from disk and forget loaded voices, so files added to the voice
+    directories are picked up. Waits for syntheses in progress to finish first;
+    being a plain function it does so in a worker thread, not on the event loop.
+    """
+    global core_model, model_config, languages, default_voices, model_generation
+    with synthesis_lock.exclusive(), pipelines_lock:
+        model_config = load_model_config()
+        core_model = load_model(model_config)
+        model_generation += 1
+        languages = load_languages()
+        default_voices = load_default_voices()
+        pipelines.clear()  # their voice caches go with them
+        g2p_pipelines.clear()
+        audio_cache.clear()
+    report_voices()
+    check_default_voice()
+    return {"voices": len(voice_files())}
+
 @app.post("/synthesize_file")
-async def synthesize_file(request: Request):
+async def synthesize_file(req: SynthesisRequest, request: Request):
+    """
+    Synthesize the full text and return it as an audio file (WAV, FLAC, MP3 or OGG).
+    With start_s/end_s only that time window of the audio is returned. With
+    format "auto" the chosen format shows in Content-Type and the filename.
+    Seeded (reproducible) requests get an ETag and Cache-Control; a matching
+    If-None-Match is answered with 304 without synthesizing anything.
+    """
+    etag = request_etag(req)
+    headers = {"ETag": etag, "Cache-Control": f"public, max-age={FILE_MAX_AGE_S}"} if etag else {}
+    if etag and etag_matches(etag, request.headers.get("If-None-Match")):
+        return Response(status_code=304, headers=headers)
+    rendered = await render_until_disconnect(req, request)
+    return file_response(encode_for_request(rendered, req), rendered.format, headers=headers)
+
+@app.post("/synthesize/mp3")
+async def synthesize_mp3(req: SynthesisRequest, request: Request):
+    """
+    Synthesize the full text and return it as an MP3 file at the request's
+    `bitrate` (kbps), whatever its `format` says.
+    """
+    return await synthesize_file(req.model_copy(update={"format": "mp3"}), request)
+
+@app.post("/synthesize/pcm")
+async def synthesize_pcm(req: SynthesisRequest, request: Request):
     """
-    Synthesize the full text and return it as a WAV file.
+    Synthesize the full text and return the raw float samples, without the
+    16/24-bit quantization of the file formats: little-endian IEEE 754 float32
+    in [-1, 1] with no header, channels interleaved (left, right, left, ...)
+    when stereo. The X-Sample-Rate and X-Channels headers give the layout.
+    """
+    rendered = await render_until_disconnect(req, request)
+    channels = 1 if rendered.audio.ndim == 1 else rendered.audio.shape[1]
+    return Response(rendered.audio.astype("<f4").tobytes(), media_type="application/octet-stream",
+                    headers={"X-Sample-Rate": str(rendered.sample_rate), "X-Channels": str(channels),
+                             "X-Sample-Format": "f32le"})
+
+@app.post("/synthesize/stream")
+async def synthesize_stream(req: SynthesisRequest):
+    """
+    Stream a WAV while it is being synthesized: the header goes out first with
+    unknown (0xFFFFFFFF) sizes, then the PCM of each segment as soon as the
+    pipeline produces it (see stream_pcm for the options that apply).
+    """
+    pcm = stream_pcm(req)
+
+    def chunks() -> Iterator[bytes]:
+        yield wav_header(req.channels, req.bit_depth, 0xFFFFFFFF, req.sample_rate or SAMPLE_RATE)
+        yield from pcm
+
+    return StreamingResponse(chunks(), media_type="audio/wav")
+
+@app.websocket("/ws")
+async def synthesize_ws(websocket: WebSocket):
+    """
+    Real-time synthesis over a WebSocket. Each text message is a JSON
+    synthesis request (`text`, `voice`, `speed`, ... as for /synthesize/stream);
+    the server answers with binary PCM frames (little-endian, interleaved
+    when stereo, at the request's sample rate, bit depth and channels) as segments are generated, then a
+    `{"event": "done"}` text message. A bad request, or one failing part way
+    (e.g. timing out), gets `{"event": "error"}` instead of "done" and the
+    connection stays open for the next one.
+    """
+    await websocket.accept()
+    try:
+        while True:
+            message = await websocket.receive_text()
+            try:
+                pcm = stream_pcm(SynthesisRequest.model_validate_json(message))
+                async for frame in iterate_in_threadpool(pcm):
+                    await websocket.send_bytes(frame)
+            except ValidationError as e:
+                await websocket.send_json({"event": "error", "status_code": 400, "error_code": "INVALID_REQUEST",
+                                           "detail": json.loads(e.json())})
+                continue
+            except HTTPException as e:
+                await websocket.send_json({"event": "error", "status_code": e.status_code,
+                                           "error_code": getattr(e, "error_code", None), "detail": e.detail})
+                continue
+            except WebSocketDisconnect:
+                raise
+            except Exception as e:
+                logger.exception("WebSocket synthesis failed")
+                metrics.failure(e)
+                await websocket.send_json({"event": "error", "status_code": 500, "error_code": "SYNTHESIS_FAILED",
+                                           "detail": "Synthesis failed"})
+                continue
+            await websocket.send_json({"event": "done"})
+    except WebSocketDisconnect:
+        pass
+
+@app.post("/synthesize/ogg")
+async def synthesize_ogg(req: SynthesisRequest, request: Request):
+    """
+    Synthesize the full text and return it as an Ogg Vorbis file, whatever
+    the request's `format` says.
+    """
+    return await synthesize_file(req.model_copy(update={"format": "ogg"}), request)
+
+@app.post("/synthesize", response_model=SynthesisResponse, response_model_exclude_none=True)
+async def synthesize_json(req: SynthesisRequest, request: Request):
     """
-    data = await request.json()
-    text = data.get("text", "").strip()
-    voice = data.get("voice", settings.voice)
+    Synthesize text and return the audio base64-encoded in a JSON body, along
+    with details about the synthesis (normalizations applied, with `explain`;
+    clipping, with `quality`).
+    """
+    return synthesis_response(await render_until_disconnect(req, request), req)
+
+def openai_voice(name: str) -> str:
+    """
+    The installed voice closest to an OpenAI voice name: the voice itself if
+    installed, else its OPENAI_VOICES counterpart, else an installed voice of
+    the same gender, else settings.voice.
+    """
+    installed = sorted(Path(v).stem for v in get_available_voices())
+    target = name if name in installed else OPENAI_VOICES.get(name.lower())
+    if target in installed:
+        return target
+    gender = voice_info(target)["gender"] if target else "unknown"
+    return next((v for v in installed if voice_info(v)["gender"] == gender), settings.voice)
+
+@app.post("/v1/audio/speech")
+async def openai_speech(req: SpeechRequest, request: Request):
+    """
+    OpenAI-compatible speech endpoint, so OpenAI TTS clients can use this server:
+    returns the raw audio in `response_format` (pcm is 16-bit mono at 24 kHz,
+    as OpenAI's is). `aac` is not available.
+    """
+    fmt = "wav" if req.response_format == "pcm" else req.response_format
+    if fmt == "aac":
+        raise APIError(400, "UNSUPPORTED_FORMAT", "Format 'aac' not supported by this server")
+    synthesis = SynthesisRequest(text=req.input, voice=openai_voice(req.voice), speed=req.speed, format=fmt)
+    rendered = await render_until_disconnect(synthesis, request)
+    if req.response_format == "pcm":
+        return Response(to_pcm(rendered.audio), media_type="audio/pcm")
+    return Response(encode_for_request(rendered, synthesis), media_type=ENCODERS[fmt][1])
+
+def token_label(symbol: str) -> str:
+    """Readable description of a phoneme symbol, e.g. "vowel 'ə'" or "primary stress"."""
+    names = {" ": "word boundary", "ˈ": "primary stress", "ˌ": "secondary stress", "ː": "long"}
+    if symbol in names:
+        return names[symbol]
+    if symbol in VOWEL_PHONEMES:
+        return f"vowel '{symbol}'"
+    if symbol in MODIFIER_PHONEMES:
+        return f"modifier '{symbol}'"
+    if symbol in SILENT_PHONEMES:
+        return f"punctuation '{symbol}'"
+    return f"consonant '{symbol}'"
+
+@app.post("/tokenize", response_model=List[TokenizedChunk], response_model_exclude_none=True)
+def tokenize(req: TokenizeRequest):
+    """
+    Show what the model is fed for a text, chunk by chunk: the normalized
+    graphemes, their phonemes, and the token IDs with a label per token (the
+    model wraps every chunk in boundary token 0). English adds per-word phonemes.
+    A plain function, so G2P runs in a worker thread.
+    """
+    text = req.text.strip()
+    voice = req.voice or default_voice(req.language)
     if not text:
-        raise HTTPException(status_code=400, detail="`text` required")
+        raise APIError(400, "EMPTY_TEXT", "`text` required")
+    check_text_length(len(text))
+    pipeline = g2p_pipeline_for_voice(voice, req.language)
+    boundary = Token(id=0, symbol="", label="boundary")
+    chunks = []
+    for result in pipeline(prepare_text(text, voice, language=req.language)[0]):
+        tokens = [Token(id=core_model.vocab.get(p), symbol=p, label=token_label(p)) for p in result.phonemes]
+        words = [WordToken(text=t.text, phonemes=t.phonemes) for t in result.tokens] if result.tokens else None
+        chunks.append(TokenizedChunk(graphemes=result.graphemes, phonemes=result.phonemes,
+                                     tokens=[boundary, *tokens, boundary], words=words))
+    return chunks
 
-    frames = []
-    for (_, _, audio) in pipeline(text, voice=voice):
-        frames.append(audio)
-    if not frames:
-        raise HTTPException(status_code=500, detail="No audio generated")
+@app.post("/debug/spectrum", response_model=Spectrum)
+async def debug_spectrum(req: SpectrumRequest, request: Request):
+    """
+    Synthesize a request and return the magnitude spectrum of the whole clip
+    (Hann-windowed, stereo mixed to mono), averaged into `bins` equal bands
+    up to the Nyquist frequency, e.g. to check where a voice's energy sits.
+    """
+    rendered = await render_until_disconnect(req, request)
+    audio = rendered.audio if rendered.audio.ndim == 1 else rendered.audio.mean(axis=1)
+    # Below two samples there is no frequency above DC to find a peak at
+    if len(audio) < 2:
+        raise APIError(400, "CLIP_TOO_SHORT", f"{len(audio)} sample(s) are too few for a spectrum")
+    window = np.hanning(len(audio))
+    power = np.abs(np.fft.rfft(audio * window)) ** 2 * 4 / np.sum(window) ** 2  # full-scale sine -> 1
+    bands = np.array_split(power, req.bins)
+    return Spectrum(
+        sample_rate=rendered.sample_rate,
+        bin_width_hz=rendered.sample_rate / 2 / req.bins,
+        magnitudes_db=[float(10 * np.log10(max(np.mean(band), 1e-12))) if len(band) else -120.0 for band in bands],
+        peak_hz=float(np.argmax(power[1:]) + 1) * rendered.sample_rate / len(audio),
+    )
+
+@app.post("/estimate", response_model=Estimate)
+def estimate(req: SynthesisRequest):
+    """
+    Estimate how long a /synthesize request's audio will be without
+    synthesizing it: only normalization and G2P run, each phoneme counts
+    ESTIMATE_PHONEME_S at the request's speed, and SSML breaks are added.
+    Effects, variation and time windows are not accounted for; expect the
+    real duration within about 20%. A plain function, so G2P runs in a
+    worker thread.
+    """
+    text = req.text.strip()
+    voice = req.voice or default_voice(req.language)
+    if not text and not req.phonemes:
+        raise APIError(400, "EMPTY_TEXT", "`text` or `phonemes` required")
+    check_text_length(len(req.phonemes or text))
+    check_sample_rate(req.sample_rate)
+    pipeline = g2p_pipeline_for_voice(voice, req.language)
+
+    def phoneme_count(part: str) -> int:
+        return sum(len(result.phonemes) for result in pipeline(part))
 
-    pcm = b"".join(f.tobytes() for f in frames)
+    if req.phonemes:
+        runs = [(len(ipa_to_phonemes(req.phonemes)), 1.0, 0.0)]
+    elif text.startswith("<speak"):
+        runs = [(phoneme_count(run.text), run.speed, run.break_ms) for run in ssml_runs(text, voice, req)[0]]
+    else:
+        runs = [(phoneme_count(prepare_text(text, voice, req)[0]), 1.0, 0.0)]
+    duration = sum(count * ESTIMATE_PHONEME_S / (req.speed * speed) + break_ms / 1000
+                   for count, speed, break_ms in runs)
+    rate = TELEPHONY_SAMPLE_RATE if req.format in ("mulaw", "alaw") else req.sample_rate or SAMPLE_RATE
+    return Estimate(duration_seconds=duration, samples=int(duration * rate), sample_rate=rate,
+                    phonemes=sum(count for count, _, _ in runs))
+
+@app.post("/synthesize/batch", response_model=List[BatchResult], response_model_exclude_none=True)
+async def synthesize_batch(batch: BatchRequest, request: Request):
+    """
+    Synthesize several requests in one call, each returned as /synthesize
+    would. Items fail independently: a failed item carries its status code and
+    error instead of a result, and every entry has the `index` of its item.
+    """
+    results = []
+    for index, req in enumerate(batch.items):
+        try:
+            rendered = await render_until_disconnect(req, request)
+            results.append(BatchResult(index=index, result=synthesis_response(rendered, req)))
+        except HTTPException as e:
+            metrics.failure(e)
+            results.append(BatchResult(index=index, status_code=e.status_code, error=str(e.detail),
+                                       error_code=getattr(e, "error_code", None)))
+        except SynthesisCancelled:
+            raise  # the client is gone; nobody wants the rest
+        except Exception as e:
+            logger.exception("Batch item %d failed", index)
+            metrics.failure(e)
+            results.append(BatchResult(index=index, status_code=500, error=str(e), error_code="SYNTHESIS_FAILED"))
+    return results
+
+@app.post("/jobs", response_model=JobStatus, response_model_exclude_none=True, status_code=202)
+async def submit_job(req: SynthesisRequest, background_tasks: BackgroundTasks):
+    """
+    Queue a synthesis and return its job ID immediately; poll GET /jobs/{id}
+    for the result, or follow GET /synthesize/progress?job_id={id}. Finished
+    jobs are kept for JOB_TTL_S. With MAX_PENDING_JOBS still pending, new
+    ones are refused with 503 QUEUE_FULL and a Retry-After.
+    """
+    prune_jobs()
+    if not req.text.strip() and not req.phonemes:
+        raise APIError(400, "EMPTY_TEXT", "`text` or `phonemes` required")
+    check_text_length(len(req.phonemes or req.text.strip()))
+    check_request_format(req)
+    check_sample_rate(req.sample_rate)
+    if sum(job.finished_at is None for job in jobs.values()) >= MAX_PENDING_JOBS:
+        raise APIError(503, "QUEUE_FULL", f"{MAX_PENDING_JOBS} jobs are already pending; retry later",
+                       headers={"Retry-After": "5"})
+    job = Job(id=uuid.uuid4().hex, format=req.format)
+    jobs[job.id] = job
+    background_tasks.add_task(run_job, job, req)
+    return job.describe()
+
+@app.get("/jobs/{job_id}")
+async def get_job(job_id: str, request: Request):
+    """
+    Return the job's status while it is pending or failed, and its audio once
+    done. The audio is stored gzipped; it is sent as is to clients accepting
+    gzip (Content-Encoding: gzip) and decompressed for everyone else.
+    """
+    prune_jobs()
+    job = jobs.get(job_id)
+    if job is None:
+        raise APIError(404, "JOB_NOT_FOUND", f"Job '{job_id}' not found")
+    if job.status != "done":
+        return job.describe().model_dump(exclude_none=True)
+    headers = {"Content-Disposition": f"attachment; filename={job.id}.{FILE_EXTENSIONS.get(job.format, job.format)}"}
+    if "gzip" in request.headers.get("Accept-Encoding", ""):
+        headers["Content-Encoding"] = "gzip"
+        return Response(job.data, media_type=ENCODERS[job.format][1], headers=headers)
+    return Response(gzip.decompress(job.data), media_type=ENCODERS[job.format][1], headers=headers)
+
+@app.get("/synthesize/progress")
+async def job_progress(job_id: str):
+    """
+    Server-sent events following the job `job_id` (from POST /jobs): `progress` events with the percent
+    synthesized (`{"percent": 40, "status": "running"}`) whenever it changes,
+    then either `done` with the URL to download the audio from or `error`
+    with the job's error, after which the stream ends.
+    """
+    job = jobs.get(job_id)
+    if job is None:
+        raise APIError(404, "JOB_NOT_FOUND", f"Job '{job_id}' not found")
+
+    def event(name: str, data: dict) -> str:
+        return f"event: {name}\ndata: {json.dumps(data)}\n\n"
+
+    async def events():
+        last = None
+        while job.status not in ("done", "failed"):
+            percent = round(job.progress * 100)
+            if percent != last:
+                yield event("progress", {"percent": percent, "status": job.status})
+                last = percent
+            await asyncio.sleep(PROGRESS_INTERVAL_S)
+        if job.status == "done":
+            yield event("done", {"percent": 100, "url": f"/jobs/{job.id}", "size_bytes": job.size_bytes})
+        else:
+            yield event("error", {"error": job.error, "error_code": job.error_code})
+
+    return StreamingResponse(events(), media_type="text/event-stream", headers={"Cache-Control": "no-cache"})
+
+@app.post("/synthesize/textgrid")
+async def synthesize_textgrid(req: SynthesisRequest, request: Request):
+    """
+    Synthesize text and return a ZIP holding the WAV plus a Praat TextGrid with
+    "words" and "phonemes" tiers aligned to it. Only text, voice, language and
+    speed apply; timing-altering options are ignored so the labels stay exact.
+    Word labels need the English G2P; other languages get phonemes only.
+    """
+    text = req.text.strip()
+    voice = req.voice or default_voice(req.language)
+    if not text:
+        raise APIError(400, "EMPTY_TEXT", "`text` required")
+    check_text_length(len(text))
+    check_voice(voice)
+    alignment = Alignment()
+    audio = await run_until_disconnect(
+        request, lambda: synthesize(prepare_text(text, voice, req)[0], voice, speed=req.speed,
+                                    alignment=alignment, language=req.language))
+    if not len(audio):
+        raise APIError(500, "SYNTHESIS_FAILED", "No audio generated")
+
+    duration = len(audio) / SAMPLE_RATE
     buf = io.BytesIO()
-    with wave.open(buf, "wb") as wf:
-        wf.setnchannels(1)
-        wf.setsampwidth(2)
-        wf.setframerate(24000)
-        wf.writeframes(pcm)
+    with zipfile.ZipFile(buf, "w", zipfile.ZIP_DEFLATED) as zf:
+        zf.writestr("output.wav", audio_to_wav(audio))
+        zf.writestr("output.TextGrid", to_textgrid(duration, {"words": alignment.words, "phonemes": alignment.phonemes}))
     buf.seek(0)
-
     return StreamingResponse(
         buf,
-        media_type="audio/wav",
-        headers={"Content-Disposition": "attachment; filename=output.wav"}
+        media_type="application/zip",
+        headers={"Content-Disposition": "attachment; filename=output.zip"}
     )
 
+@app.post("/synthesize/rich")
+async def synthesize_rich(doc: RichDocument, request: Request):
+    """
+    Synthesize a structured rich-text document into one continuous WAV:
+    each paragraph uses its own voice, and each run its own emphasis, pitch
+    and trailing pause.
+    """
+    check_text_length(sum(len(run.text) for paragraph in doc.paragraphs for run in paragraph.runs))
+    for paragraph in doc.paragraphs:
+        check_voice(paragraph.voice or settings.voice)
+    audio = await run_until_disconnect(request, render_rich, doc)
+    if not len(audio) or not np.any(audio):
+        raise APIError(400, "EMPTY_TEXT", "Document contains no text")
+    return audio_response(audio)
+
+@app.get("/test-sentences")
+async def test_sentences(
+    request: Request,
+    voice: Optional[str] = None,
+    index: Optional[int] = Query(None, ge=0, lt=len(TEST_SENTENCES)),
+):
+    """
+    Synthesize one of the built-in Harvard sentences (by `index`), or all of
+    them in sequence, with the given voice. Useful for evaluating a voice
+    across the full phoneme inventory.
+    """
+    voice = voice or settings.voice
+    check_voice(voice)
+    sentences = TEST_SENTENCES if index is None else [TEST_SENTENCES[index]]
+    audio = await run_until_disconnect(request, lambda: synthesize(prepare_text("\n".join(sentences), voice)[0], voice))
+    if not len(audio):
+        raise APIError(500, "SYNTHESIS_FAILED", "No audio generated")
+    return audio_response(audio, name="test_sentences")
+
 @app.post("/play_live")
-async def play_live(request: Request):
+async def play_live(req: SynthesisRequest, request: Request):
     """
     Synthesize text and play audio live on the server using sounddevice.
+    Generation and playback run in a worker thread, like any other render.
     """
-    data = await request.json()
-    text = data.get("text", "").strip()
-    voice = data.get("voice", settings.voice)
+    text = req.text.strip()
+    voice = req.voice or default_voice(req.language)
     if not text:
-        raise HTTPException(status_code=400, detail="`text` required")
+        raise APIError(400, "EMPTY_TEXT", "`text` required")
+    check_text_length(len(text))
+    check_voice(voice)
 
-    for (_, _, audio) in pipeline(text, voice=voice):
-        sd.play(audio, samplerate=24000)
+    def play():
+        audio = synthesize(prepare_text(text, voice, req)[0], voice, speed=req.speed, language=req.language)
+        sd.play(audio, samplerate=SAMPLE_RATE)
         sd.wait()
 
+    await run_until_disconnect(request, play)
     return {"status": "played"}
 
-if __name__ == "__main__":
+def main():
+    """Entry point (the `kokorofile` script): serve the API on --host/--port."""
+    global MAX_TEXT_LENGTH, voices_dir
+    import argparse
+    import socket
+    import sys
     import uvicorn
-    uvicorn.run(app, host="0.0.0.0", port=8000)
+
+    parser = argparse.ArgumentParser(description="Kokoro TTS API server")
+    parser.add_argument("--host", default="0.0.0.0", help="Address to bind (default: all interfaces)")
+    parser.add_argument("--port", type=int, default=8000, help="Port to listen on (default: 8000)")
+    parser.add_argument("--voices-dir", type=Path, default=voices_dir,
+                        help=f"Extra directory of .pt voices, overriding those in {VOICE_DIR}")
+    parser.add_argument("--default-voice",
+                        help=f"Voice for requests that name none (default: {settings.voice}, "
+                             "else the first installed voice)")
+    parser.add_argument("--log-level", default=logging.getLevelName(logger.level).lower(),
+                        choices=["debug", "info", "warning", "error"], help="Logging verbosity (default: info)")
+    parser.add_argument("--log-format", default="json" if isinstance(log_handler.formatter, JsonFormatter) else "text",
+                        choices=list(LOG_FORMATS), help="text lines, or one JSON object per line for log ingestion")
+    parser.add_argument("--rate-limit", type=int, default=RATE_LIMIT, metavar="PER_MINUTE",
+                        help="Requests per minute allowed per client IP (default: 0, unlimited)")
+    parser.add_argument("--max-text-length", type=int, default=MAX_TEXT_LENGTH,
+                        help=f"Longest text accepted, in characters (default: {MAX_TEXT_LENGTH})")
+    args = parser.parse_args()
+    configure_logging(args.log_level, args.log_format)
+    rate_limiter.per_minute = args.rate_limit
+    MAX_TEXT_LENGTH = args.max_text_length
+    if args.voices_dir != voices_dir:
+        voices_dir = args.voices_dir
+        report_voices()
+    if args.default_voice:
+        settings.voice = args.default_voice
+    check_default_voice()
+
+    # Check the address up front so a clash fails with one clear message
+    try:
+        with socket.create_server((args.host, args.port)):
+            pass
+    except OSError as e:
+        sys.exit(f"Cannot bind {args.host}:{args.port}: {e.strerror or e}")
+    logger.info("Kokoro TTS API listening on http://%s:%d", args.host, args.port)
+    uvicorn.run(app, host=args.host, port=args.port)
+
+if __name__ == "__main__":
+    main()
 
@@ -0,0 +1,100 @@
+"""
+A subset of SSML for inline prosody control: <speak> documents with
+<break>, <emphasis> and <prosody rate>. The markup is flattened into runs of
+text that share one style, each followed by an optional pause.
+"""
+import re
+import xml.etree.ElementTree as ET
+from dataclasses import dataclass
+from typing import List
+
+@dataclass
+class SsmlRun:
+    text: str
+    level: int = 0          # +1 emphasized, -1 reduced
+    rate: float = 1.0       # Speed factor relative to the request's speed
+    break_ms: float = 0.0   # Pause after the run
+
+class SsmlError(ValueError):
+    pass
+
+RATES = {"x-slow": 0.5, "slow": 0.75, "medium": 1.0, "fast": 1.25, "x-fast": 1.5, "default": 1.0}
+BREAK_STRENGTHS = {"none": 0, "x-weak": 100, "weak": 250, "medium": 400, "strong": 700, "x-strong": 1000}
+EMPHASIS_LEVELS = {"strong": 1, "moderate": 1, "none": 0, "reduced": -1}
+MAX_BREAK_MS = 10000
+
+def parse_rate(value: str) -> float:
+    """"slow", "80%" (of normal) or "+20%" (relative change)."""
+    value = value.strip()
+    if value in RATES:
+        return RATES[value]
+    match = re.fullmatch(r"([+-]?)(\d+(?:\.\d+)?)%", value)
+    if not match:
+        raise SsmlError(f"Invalid prosody rate '{value}'")
+    sign, number = match.group(1), float(match.group(2)) / 100
+    rate = 1.0 + number if sign == "+" else 1.0 - number if sign == "-" else number
+    if not 0.25 <= rate <= 4.0:
+        raise SsmlError(f"Prosody rate '{value}' is outside 25%-400%")
+    return rate
+
+def parse_break(element: ET.Element) -> float:
+    """Pause length of a <break>: its `time` ("500ms", "1.5s"), else its `strength`."""
+    time = element.get("time")
+    if time is not None:
+        match = re.fullmatch(r"(\d+(?:\.\d+)?)(ms|s)", time.strip())
+        if not match:
+            raise SsmlError(f"Invalid break time '{time}'")
+        return float(match.group(1)) * (1000 if match.group(2) == "s" else 1)
+    strength = element.get("strength", "medium")
+    if strength not in BREAK_STRENGTHS:
+        raise SsmlError(f"Invalid break strength '{strength}'")
+    return BREAK_STRENGTHS[strength]
+
+def tag_name(element: ET.Element) -> str:
+    return element.tag.rsplit("}", 1)[-1]  # without the SSML namespace
+
+def parse_ssml(markup: str) -> List[SsmlRun]:
+    """Flatten a <speak> document into styled runs; raises SsmlError with a readable reason."""
+    try:
+        root = ET.fromstring(markup)
+    except ET.ParseError as e:
+        raise SsmlError(f"Invalid SSML: {e}") from None
+    if tag_name(root) != "speak":
+        raise SsmlError("SSML must have a <speak> root element")
+
+    runs: List[SsmlRun] = []
+
+    def add_text(text: str | None, level: int, rate: float):
+        if not text:
+            return
+        last = runs[-1] if runs else None
+        if last and not last.break_ms and (last.level, last.rate) == (level, rate):
+            last.text += text
+        elif text.strip():
+            runs.append(SsmlRun(text, level, rate))
+
+    def walk(element: ET.Element, level: int, rate: float):
+        add_text(element.text, level, rate)
+        for child in element:
+            name = tag_name(child)
+            if name == "break":
+                if child.text and child.text.strip() or len(child):
+                    raise SsmlError("<break> must be empty")
+                if not runs:
+                    runs.append(SsmlRun(""))
+                runs[-1].break_ms += parse_break(child)
+                if runs[-1].break_ms > MAX_BREAK_MS:
+                    raise SsmlError(f"Pauses longer than {MAX_BREAK_MS} ms in a row")
+            elif name == "emphasis":
+                emphasis = child.get("level", "moderate")
+                if emphasis not in EMPHASIS_LEVELS:
+                    raise SsmlError(f"Invalid emphasis level '{emphasis}'")
+                walk(child, EMPHASIS_LEVELS[emphasis], rate)
+            elif name == "prosody":
+                walk(child, level, rate * parse_rate(child.get("rate", "medium")))
+            else:
+                raise SsmlError(f"Unsupported SSML element <{name}>")
+            add_text(child.tail, level, rate)
+
+    walk(root, 0, 1.0)
+    return runs
@@ -0,0 +1,71 @@
+"""
+server.py loads the Kokoro model and voices when imported, so the tests run
+it against a stand-in `kokoro` package (a tone per phoneme instead of speech)
+in a scratch directory holding a fake checkpoint, config and voice.
+"""
+import json
+import os
+import sys
+import tempfile
+import types
+from pathlib import Path
+
+import numpy as np
+import pytest
+import torch
+
+SAMPLE_RATE = 24000
+SAMPLES_PER_PHONEME = 1200  # Fake pipeline output: 50 ms per phoneme at speed 1
+VOCAB = {symbol: i for i, symbol in enumerate(" abcdefghijklmnopqrstuvwxyz.,!?", start=1)}
+VOICE = "af_test"
+
+class FakeResult:
+    def __init__(self, graphemes: str, phonemes: str, audio: np.ndarray):
+        self.graphemes = graphemes
+        self.phonemes = phonemes
+        self.audio = audio
+        self.tokens = None
+        self.pred_dur = None
+
+    def __iter__(self):
+        return iter((self.graphemes, self.phonemes, self.audio))
+
+def tone(phonemes: str, speed: float) -> np.ndarray:
+    n = int(len(phonemes) * SAMPLES_PER_PHONEME / speed)
+    return (0.5 * np.sin(2 * np.pi * 220 * np.arange(n) / SAMPLE_RATE)).astype(np.float32)
+
+class FakeKModel:
+    def __init__(self, repo_id=None, config=None, model=None):
+        self.vocab = config["vocab"]
+
+class FakeKPipeline:
+    """G2P lowercases the text; each phoneme becomes SAMPLES_PER_PHONEME of a 220 Hz tone."""
+
+    def __init__(self, lang_code, model=True, **kwargs):
+        self.lang_code = lang_code
+        self.g2p = lambda text: "".join(c for c in text.lower() if c in VOCAB)
+
+    def __call__(self, text, voice=None, speed=1.0):
+        phonemes = self.g2p(text)
+        yield FakeResult(text, phonemes, tone(phonemes, speed))
+
+    def generate_from_tokens(self, tokens, voice=None, speed=1.0):
+        yield FakeResult("", tokens, tone(tokens, speed))
+
+sys.modules["kokoro"] = types.SimpleNamespace(KModel=FakeKModel, KPipeline=FakeKPipeline)
+sys.modules["sounddevice"] = types.SimpleNamespace(play=lambda *a, **k: None, wait=lambda: None)
+
+scratch = Path(tempfile.mkdtemp(prefix="kokorofile-tests-"))
+(scratch / "models" / "voices").mkdir(parents=True)
+(scratch / "models" / "kokoro-v1_0.pth").touch()
+(scratch / "models" / "config.json").write_text(json.dumps({"vocab": VOCAB, "n_token": len(VOCAB) + 1}))
+torch.save(torch.zeros(510, 1, 256), scratch / "models" / "voices" / f"{VOICE}.pt")
+os.chdir(scratch)  # server.py resolves models/ relative to the working directory
+
+import server  # noqa: E402
+
+@pytest.fixture
+def client():
+    from fastapi.testclient import TestClient
+    server.audio_cache.clear()
+    return TestClient(server.app)
@@ -0,0 +1,43 @@
+import pytest
+
+import server
+from conftest import VOICE
+from normalize import normalize_text
+
+def cardinal(text: str) -> str:
+    return normalize_text(text, "en", number_mode="cardinal", number_lang="en")[0]
+
+@pytest.mark.parametrize("text, expected", [
+    ("I have 123 apples", "I have one hundred and twenty-three apples"),
+    ("It costs 1,000", "It costs one thousand"),
+    ("Add 2.5 cups", "Add two point five cups"),
+])
+def test_cardinal_numbers(text, expected):
+    assert cardinal(text) == expected
+
+@pytest.mark.parametrize("text, expected", [
+    ("the 1st time", "the first time"),
+    ("on the 21st floor", "on the twenty-first floor"),
+    ("his 103rd birthday", "his one hundred and third birthday"),
+])
+def test_ordinals(text, expected):
+    assert cardinal(text) == expected
+
+@pytest.mark.parametrize("text, expected", [
+    ("$5.50", "five dollars fifty cents"),
+    ("$1", "one dollar"),
+    ("$2,000.00", "two thousand dollars"),
+])
+def test_currency(text, expected):
+    assert cardinal(text) == expected
+
+def test_abbreviations_are_recorded():
+    text, applied = normalize_text("Dr. Smith lives on Baker St.", "en")
+    assert text == "Doctor Smith lives on Baker Street."
+    assert [(a.source, a.target, a.kind) for a in applied] == [
+        ("Dr.", "Doctor", "abbreviation"), ("St.", "Street.", "abbreviation"),
+    ]
+
+def test_normalization_can_be_switched_off():
+    req = server.SynthesisRequest(text="Dr. Smith has 3 cats", number_mode="cardinal", normalize=False)
+    assert server.prepare_text(req.text, VOICE, req) == ("Dr. Smith has 3 cats", [])
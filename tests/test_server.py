@@ -0,0 +1,493 @@
+import base64
+import io
+import json
+import logging
+import os
+import struct
+import threading
+import zipfile
+from concurrent.futures import ThreadPoolExecutor
+
+import numpy as np
+import pytest
+import torch
+
+import server
+from conftest import SAMPLES_PER_PHONEME, VOCAB, VOICE, FakeKPipeline
+
+def riff_chunks(data: bytes) -> dict:
+    """The chunks of a RIFF/WAVE file by id, checking the outer header on the way."""
+    assert data[:4] == b"RIFF" and data[8:12] == b"WAVE"
+    assert struct.unpack("<I", data[4:8])[0] == len(data) - 8
+    chunks, pos = {}, 12
+    while pos < len(data):
+        chunk_id, size = data[pos:pos + 4], struct.unpack("<I", data[pos + 4:pos + 8])[0]
+        chunks[chunk_id] = data[pos + 8:pos + 8 + size]
+        pos += 8 + size
+    return chunks
+
+def dominant_frequency(audio: np.ndarray, rate: int) -> float:
+    spectrum = np.abs(np.fft.rfft(audio * np.hanning(len(audio))))
+    return np.argmax(spectrum) * rate / len(audio)
+
+def test_synthesize_returns_one_frame_run_per_phoneme():
+    audio = server.synthesize("Hello world", VOICE)
+    assert audio.dtype == np.float32
+    assert len(audio) == len("hello world") * SAMPLES_PER_PHONEME
+
+def test_synthesize_speed_shortens_audio():
+    assert len(server.synthesize("Hello world", VOICE, speed=2.0)) == len("hello world") * SAMPLES_PER_PHONEME // 2
+
+def test_seeded_passes_repeat_without_touching_the_global_rng(monkeypatch):
+    real = FakeKPipeline.__call__
+
+    def noisy(self, text, voice=None, speed=1.0):
+        for result in real(self, text, voice, speed):
+            result.audio = result.audio + 0.01 * torch.randn(len(result.audio)).numpy()  # like the vocoder
+            yield result
+
+    monkeypatch.setattr(FakeKPipeline, "__call__", noisy)
+    torch.manual_seed(0)
+    expected = torch.rand(1)
+    torch.manual_seed(0)
+    first = server.synthesize("Hello", VOICE, seed=7)
+    assert torch.rand(1) == expected
+    assert np.array_equal(first, server.synthesize("Hello", VOICE, seed=7))
+    assert not np.array_equal(first, server.synthesize("Hello", VOICE, seed=8))
+
+def test_unseeded_syntheses_run_side_by_side(monkeypatch):
+    real = FakeKPipeline.__call__
+    both_generating = threading.Barrier(2, timeout=5)  # broken if one pass waits for the other
+
+    def meet(self, text, voice=None, speed=1.0):
+        both_generating.wait()
+        yield from real(self, text, voice, speed)
+
+    monkeypatch.setattr(FakeKPipeline, "__call__", meet)
+    with ThreadPoolExecutor(2) as pool:
+        clips = list(pool.map(lambda text: server.synthesize(text, VOICE), ["Hello", "World"]))
+    assert [len(clip) for clip in clips] == [5 * SAMPLES_PER_PHONEME] * 2
+
+def test_paused_stream_does_not_hold_the_synthesis_lock():
+    segments = server.pipeline_segments("Hello. World.", VOICE)
+    next(segments)  # a stream whose client is slow to read the next chunk
+    assert server.synthesis_lock.readers == 0 and not server.synthesis_lock.writing
+    segments.close()
+
+def test_reload_while_a_stream_is_open(client):
+    segments = server.pipeline_segments("Hello. World.", VOICE)
+    next(segments)  # a stream in progress
+    assert client.post("/reload").json() == {"voices": 1}
+    segments.close()
+
+def test_synthesize_endpoint_reports_duration(client):
+    response = client.post("/synthesize", json={"text": "Hello world", "voice": VOICE})
+    assert response.status_code == 200
+    body = response.json()
+    assert body["sample_rate"] == 24000
+    assert body["duration_seconds"] == pytest.approx(len("hello world") * SAMPLES_PER_PHONEME / 24000)
+    assert riff_chunks(base64.b64decode(body["audio_data"]))[b"data"]
+
+def test_synthesize_rejects_empty_text(client):
+    response = client.post("/synthesize", json={"text": "  ", "voice": VOICE})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "EMPTY_TEXT"
+
+def test_tokenize_wraps_chunks_in_boundary_tokens(client):
+    response = client.post("/tokenize", json={"text": "Hi there", "voice": VOICE})
+    assert response.status_code == 200
+    (chunk,) = response.json()
+    first, *inner, last = chunk["tokens"]
+    assert first == last == {"id": 0, "symbol": "", "label": "boundary"}
+    assert [t["id"] for t in inner] == [VOCAB[p] for p in "hi there"]
+
+def test_audio_to_wav_header():
+    chunks = riff_chunks(server.audio_to_wav(np.zeros(100, dtype=np.float32)))
+    tag, channels, rate, byte_rate, block_align, bits = struct.unpack("<HHIIHH", chunks[b"fmt "])
+    assert (tag, channels, rate, byte_rate, block_align, bits) == (1, 1, 24000, 48000, 2, 16)
+    assert len(chunks[b"data"]) == 200
+
+def test_audio_to_wav_stereo_is_extensible():
+    chunks = riff_chunks(server.audio_to_wav(np.zeros((100, 2), dtype=np.float32), bit_depth=24))
+    tag, channels, _, _, block_align, bits = struct.unpack("<HHIIHH", chunks[b"fmt "][:16])
+    assert (tag, channels, block_align, bits) == (0xFFFE, 2, 6, 24)
+    assert len(chunks[b"data"]) == 600
+
+@pytest.mark.parametrize("rate", [8000, 22050, 44100, 48000])
+def test_resample_preserves_frequency(rate):
+    sine = np.sin(2 * np.pi * 1000 * np.arange(24000) / 24000).astype(np.float32)
+    out = server.resample(sine, 24000, rate)
+    assert len(out) == rate
+    assert dominant_frequency(out, rate) == pytest.approx(1000, abs=2)
+
+def test_resample_filters_above_new_nyquist():
+    # 6 kHz is above 8 kHz's Nyquist frequency and would alias to 2 kHz
+    sine = np.sin(2 * np.pi * 6000 * np.arange(24000) / 24000).astype(np.float32)
+    out = server.resample(sine, 24000, 8000)
+    assert np.sqrt(np.mean(out[1000:-1000] ** 2)) < 1e-3
+
+def test_debug_spectrum_peaks_at_the_tone(client):
+    response = client.post("/debug/spectrum", json={"text": "Hello world", "voice": VOICE, "bins": 64})
+    assert response.status_code == 200
+    body = response.json()
+    assert len(body["magnitudes_db"]) == 64
+    assert body["peak_hz"] == pytest.approx(220, abs=5)  # the fake pipeline's tone
+    assert np.argmax(body["magnitudes_db"]) == int(220 // body["bin_width_hz"])
+
+def test_debug_spectrum_rejects_a_one_sample_clip(client):
+    response = client.post("/debug/spectrum", json={"text": "Hello world", "voice": VOICE, "start_s": 0, "end_s": 0.00004})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "CLIP_TOO_SHORT"
+
+def test_voices_lists_parsed_names(client):
+    assert client.get("/voices").json() == [
+        {"name": VOICE, "language": "American English", "accent": "en-us", "gender": "female", "speaker": "test"},
+    ]
+    assert client.get("/voices", params={"format": "names"}).json() == [VOICE]
+
+def test_voice_info_of_unconventional_name():
+    assert server.voice_info("narrator") == {
+        "language": "unknown", "accent": "unknown", "gender": "unknown", "speaker": "unknown",
+    }
+
+def test_remove_dc_centres_an_offset_buffer():
+    t = np.arange(24000) / 24000
+    audio = (0.3 + 0.2 * np.sin(2 * np.pi * 440 * t)).astype(np.float32)
+    out = server.remove_dc(audio)
+    assert abs(np.mean(out[4800:])) < 1e-3
+    assert np.sqrt(np.mean(out[4800:] ** 2)) == pytest.approx(0.2 / np.sqrt(2), rel=0.01)
+
+def test_estimate_counts_phonemes_without_synthesizing(client):
+    body = client.post("/estimate", json={"text": "Hello world", "voice": VOICE, "speed": 2.0}).json()
+    assert body["phonemes"] == len("hello world")
+    assert body["duration_seconds"] == pytest.approx(len("hello world") * server.ESTIMATE_PHONEME_S / 2)
+    assert body["samples"] == int(body["duration_seconds"] * 24000)
+
+def test_non_ascii_text_is_measured_in_characters(client, monkeypatch):
+    text = "café résumé"  # 11 characters, 13 bytes of UTF-8
+    monkeypatch.setattr(server, "MAX_TEXT_LENGTH", len(text))
+    (chunk,) = client.post("/tokenize", json={"text": text, "voice": VOICE}).json()
+    assert chunk["graphemes"] == text and chunk["phonemes"] == "caf rsum"  # the fake G2P drops é
+    assert client.post("/estimate", json={"text": text, "voice": VOICE}).json()["phonemes"] == len("caf rsum")
+    server.check_text_length(len(text))
+    response = client.post("/tokenize", json={"text": text + "s", "voice": VOICE})
+    assert response.status_code == 413 and "12 characters" in response.json()["detail"]
+
+def test_language_selects_g2p_and_rejects_unknown(client):
+    (chunk,) = client.post("/tokenize", json={"text": "hola", "voice": VOICE, "language": "es"}).json()
+    assert chunk["phonemes"] == "hola"
+    assert server.pipelines.get("e") is None and "e" in server.g2p_pipelines
+    response = client.post("/tokenize", json={"text": "hola", "voice": VOICE, "language": "xx"})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "UNSUPPORTED_LANGUAGE"
+    assert "es" in response.json()["detail"]
+
+def test_warmup_fills_the_cache(client):
+    body = client.post("/warmup", json={"phrases": ["Hello", "Goodbye"], "options": {"voice": VOICE}}).json()
+    assert body["warmed"] == 2
+    assert body["cache_bytes"] == (len("hello") + len("goodbye")) * SAMPLES_PER_PHONEME * 4
+    response = client.post("/synthesize", json={"text": "Hello", "voice": VOICE})
+    assert response.json()["cache_hit"] is True
+
+def test_json_log_lines_carry_extra_fields():
+    record = logging.makeLogRecord({"msg": "Generated %.2fs", "args": (1.5,), "levelname": "INFO",
+                                    "request_id": "abc", "voice": VOICE})
+    entry = json.loads(server.JsonFormatter().format(record))
+    assert entry["message"] == "Generated 1.50s"
+    assert (entry["level"], entry["request_id"], entry["voice"]) == ("INFO", "abc", VOICE)
+
+def test_file_etag_answers_repeat_requests_with_304(client):
+    body = {"text": "Hello world", "voice": VOICE, "seed": 1}
+    first = client.post("/synthesize_file", json=body)
+    etag = first.headers["ETag"]
+    assert first.status_code == 200 and "max-age" in first.headers["Cache-Control"]
+    repeat = client.post("/synthesize_file", json=body, headers={"If-None-Match": etag})
+    assert repeat.status_code == 304 and not repeat.content
+    other = client.post("/synthesize_file", json={**body, "speed": 1.5}, headers={"If-None-Match": etag})
+    assert other.status_code == 200 and other.headers["ETag"] != etag
+    unseeded = client.post("/synthesize_file", json={"text": "Hello world", "voice": VOICE})
+    assert "ETag" not in unseeded.headers and "Cache-Control" not in unseeded.headers
+
+def test_file_etag_changes_with_the_model_and_voice_file(client):
+    body = {"text": "Hello world", "voice": VOICE, "seed": 1}
+    etag = client.post("/synthesize_file", json=body).headers["ETag"]
+    client.post("/reload")
+    reloaded = client.post("/synthesize_file", json=body, headers={"If-None-Match": etag})
+    assert reloaded.status_code == 200 and reloaded.headers["ETag"] != etag
+    stat = server.voice_files()[VOICE].stat()
+    os.utime(server.voice_files()[VOICE], ns=(stat.st_atime_ns, stat.st_mtime_ns + 10 ** 9))
+    replaced = client.post("/synthesize_file", json=body, headers={"If-None-Match": reloaded.headers["ETag"]})
+    assert replaced.status_code == 200 and replaced.headers["ETag"] != reloaded.headers["ETag"]
+
+def test_vibrato_swings_pitch_and_keeps_duration():
+    sine = np.sin(2 * np.pi * 220 * np.arange(24000) / 24000).astype(np.float32)
+    out = server.apply_vibrato(sine, rate_hz=1.0, depth_semitones=1.0)
+    assert len(out) == len(sine)
+
+    def frequency_around(t: float) -> float:
+        window = out[int((t - 0.1) * 24000):int((t + 0.1) * 24000)]
+        return np.count_nonzero(np.diff(np.signbit(window))) / 2 / 0.2
+
+    assert frequency_around(0.25) == pytest.approx(220 * 2 ** (1 / 12), abs=4)  # crest of the swing
+    assert frequency_around(0.75) == pytest.approx(220 * 2 ** (-1 / 12), abs=4)  # trough
+    assert server.apply_vibrato(sine, 5.5, 0.0) is sine
+
+def test_soft_limit_bends_only_loud_samples():
+    audio = np.array([0.1, -0.5, 0.8, 0.9, -1.5, 4.0], dtype=np.float32)
+    out = server.soft_limit(audio)
+    assert np.array_equal(out[:3], audio[:3])  # quiet samples are untouched
+    assert np.all(np.abs(out) <= 1.0)
+    assert 0.8 < out[3] < 0.9 and -1.0 < out[4] < -0.8
+    assert out[5] > out[3]  # still monotonic
+
+def test_pcm_endpoint_returns_interleaved_float32(client):
+    response = client.post("/synthesize/pcm", json={"text": "Hello", "voice": VOICE, "channels": 2, "pan": 1.0})
+    assert response.headers["X-Sample-Rate"] == "24000" and response.headers["X-Channels"] == "2"
+    frames = np.frombuffer(response.content, dtype="<f4").reshape(-1, 2)
+    assert len(frames) == len("hello") * SAMPLES_PER_PHONEME
+    assert not np.any(frames[:, 0]) and np.any(frames[:, 1])  # panned hard right
+
+def test_rate_limit_answers_429_with_retry_after(client, monkeypatch):
+    monkeypatch.setattr(server, "rate_limiter", server.RateLimiter(2))
+    assert [client.get("/voices").status_code for _ in range(2)] == [200, 200]
+    response = client.get("/voices")
+    assert response.status_code == 429
+    assert response.json()["error_code"] == "RATE_LIMITED"
+    assert response.headers["Retry-After"] == "30"
+    assert client.get("/status").status_code == 200  # exempt
+
+def test_progress_stream_ends_with_the_download_url(client):
+    job = client.post("/jobs", json={"text": "Hello world", "voice": VOICE}).json()
+    assert client.get(f"/jobs/{job['id']}").headers["Content-Type"] == "audio/wav"
+    response = client.get("/synthesize/progress", params={"job_id": job["id"]})
+    assert response.headers["Content-Type"].startswith("text/event-stream")
+    (event,) = response.text.strip().split("\n\n")  # the job finished before the stream opened
+    name, data = event.split("\n")
+    assert name == "event: done"
+    assert json.loads(data.removeprefix("data: "))["url"] == f"/jobs/{job['id']}"
+    assert client.get("/synthesize/progress", params={"job_id": "missing"}).status_code == 404
+
+def test_rich_document_checks_paragraph_voices(client):
+    doc = {"paragraphs": [{"runs": [{"text": "Hello"}]}, {"voice": "zz_nobody", "runs": [{"text": "World"}]}]}
+    response = client.post("/synthesize/rich", json=doc)
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_VOICE"
+    doc["paragraphs"][1]["voice"] = VOICE
+    assert riff_chunks(client.post("/synthesize/rich", json=doc).content)[b"data"]
+
+def test_textgrid_follows_the_request_language(client):
+    server.pipelines.pop("e", None)
+    response = client.post("/synthesize/textgrid", json={"text": "hola", "voice": VOICE, "language": "es"})
+    assert response.status_code == 200
+    assert "e" in server.pipelines  # the Spanish G2P, not the voice's
+    with zipfile.ZipFile(io.BytesIO(response.content)) as zf:
+        assert sorted(zf.namelist()) == ["output.TextGrid", "output.wav"]
+
+def test_test_sentences_reject_unknown_voices(client):
+    response = client.get("/test-sentences", params={"voice": "zz_nobody", "index": 0})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_VOICE"
+    assert riff_chunks(client.get("/test-sentences", params={"voice": VOICE, "index": 0}).content)[b"data"]
+
+def test_voice_profile_measures_the_sample(client):
+    body = client.get(f"/voices/{VOICE}/profile").json()
+    assert body["embedding_shape"] == [510, 1, 256]
+    assert body["pitch"]["median_hz"] == pytest.approx(220, rel=0.05)  # the fake pipeline's tone
+    assert client.get("/voices/zz_nobody/profile").status_code == 404
+
+def test_gzipped_file_gets_its_own_etag(client):
+    body = {"text": "Hello world", "voice": VOICE, "seed": 1}
+    plain = client.post("/synthesize_file", json=body, headers={"Accept-Encoding": "identity"})
+    zipped = client.post("/synthesize_file", json=body, headers={"Accept-Encoding": "gzip"})
+    assert zipped.headers["Content-Encoding"] == "gzip" and zipped.headers["Vary"] == "Accept-Encoding"
+    assert zipped.headers["ETag"] == plain.headers["ETag"].removesuffix('"') + '-gzip"'
+    repeat = client.post("/synthesize_file", json=body, headers={"If-None-Match": zipped.headers["ETag"]})
+    assert repeat.status_code == 304
+
+def test_voice_must_be_installed_not_just_a_file(client):
+    response = client.post("/synthesize", json={"text": "Hello", "voice": "models/config.json"})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_VOICE"
+    assert server.resolve_voice(f"{VOICE}.pt") == str(server.voice_files()[VOICE])
+
+def test_routing_errors_carry_an_error_code(client):
+    assert client.get("/no-such-route").json()["error_code"] == "NOT_FOUND"
+    response = client.get("/synthesize")
+    assert response.status_code == 405
+    assert response.json()["error_code"] == "METHOD_NOT_ALLOWED"
+
+def test_websocket_reports_errors_raised_mid_stream(client, monkeypatch):
+    request = json.dumps({"text": "Hello", "voice": VOICE})
+    with client.websocket_connect("/ws") as ws:
+        monkeypatch.setattr(server, "MAX_SYNTHESIS_S", 0)
+        ws.send_text(request)
+        assert ws.receive_bytes()  # the first segment, before the deadline is checked
+        assert ws.receive_json()["error_code"] == "SYNTHESIS_TIMEOUT"
+        monkeypatch.setattr(server, "MAX_SYNTHESIS_S", 120)
+        ws.send_text(request)  # the connection is still usable
+        assert ws.receive_bytes()
+        assert ws.receive_json() == {"event": "done"}
+
+def test_stream_rejects_incompatible_blend_up_front(client, monkeypatch, tmp_path):
+    torch.save(torch.zeros(10, 1, 128), tmp_path / "bf_odd.pt")
+    monkeypatch.setattr(server, "voices_dir", tmp_path)
+    response = client.post("/synthesize/stream", json={"text": "Hello", "voice": VOICE, "voice2": "bf_odd"})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_VOICE"
+
+def test_finished_jobs_expire(client, monkeypatch):
+    job = client.post("/jobs", json={"text": "Hello", "voice": VOICE}).json()
+    assert client.get(f"/jobs/{job['id']}").status_code == 200
+    monkeypatch.setattr(server, "JOB_TTL_S", 0)
+    assert client.get(f"/jobs/{job['id']}").json()["error_code"] == "JOB_NOT_FOUND"
+    assert job["id"] not in server.jobs
+
+def test_jobs_are_refused_while_the_queue_is_full(client, monkeypatch):
+    monkeypatch.setattr(server, "MAX_PENDING_JOBS", 1)
+    monkeypatch.setitem(server.jobs, "pending", server.Job(id="pending", format="wav", status="running"))
+    response = client.post("/jobs", json={"text": "Hello", "voice": VOICE})
+    assert response.status_code == 503 and response.headers["Retry-After"]
+    assert response.json()["error_code"] == "QUEUE_FULL"
+    server.jobs["pending"].finished_at = 0.0
+    assert client.post("/jobs", json={"text": "Hello", "voice": VOICE}).status_code == 202
+
+def post_raw_numbers(client, fields: dict):
+    """POST /synthesize with `fields` written as bare JSON tokens, so NaN and Infinity get through."""
+    body = json.dumps({"text": "Hello", "voice": VOICE}).removesuffix("}") + ", " + ", ".join(
+        f'"{k}": {v}' for k, v in fields.items()) + "}"
+    return client.post("/synthesize", content=body, headers={"Content-Type": "application/json"})
+
+@pytest.mark.parametrize("window", [{"start_s": -1.0}, {"end_s": "NaN"}, {"end_s": "Infinity"}, {"max_pause_ms": -5}])
+def test_time_fields_reject_invalid_numbers(client, window):
+    response = post_raw_numbers(client, window)
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_REQUEST"
+
+def test_window_must_be_ordered(client):
+    response = client.post("/synthesize", json={"text": "Hello", "voice": VOICE, "start_s": 0.2, "end_s": 0.1})
+    assert response.json()["error_code"] == "INVALID_WINDOW"
+
+def test_opus_rejects_rates_its_encoder_lacks(client):
+    response = client.post("/synthesize_file", json={"text": "Hello", "voice": VOICE, "format": "opus",
+                                                     "sample_rate": 44100})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "UNSUPPORTED_SAMPLE_RATE"
+
+def test_unknown_output_format_is_a_bad_request(client):
+    response = client.post("/synthesize_file", json={"text": "Hello", "voice": VOICE, "format": "aiff"})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "UNSUPPORTED_FORMAT"
+
+def test_raising_pitch_filters_instead_of_aliasing():
+    t = np.arange(24000) / 24000
+    out = server.change_playback_rate(np.sin(2 * np.pi * 1000 * t).astype(np.float32), 1.5)
+    assert len(out) == 16000
+    assert dominant_frequency(out, 24000) == pytest.approx(1500, abs=3)
+    # 10.5 kHz played 1.5x faster is 15.75 kHz, past Nyquist: it would alias to 8.25 kHz
+    high = server.change_playback_rate(np.sin(2 * np.pi * 10500 * t).astype(np.float32), 1.5)
+    assert np.sqrt(np.mean(high[1000:-1000] ** 2)) < 1e-3
+
+def test_quality_level_low_skips_vibrato_and_resamples_linearly(client):
+    sine = np.sin(2 * np.pi * 220 * np.arange(24000) / 24000).astype(np.float32)
+    plain = {"vibrato_depth": 1.0, "remove_dc": False, "soft_limit": False}
+    assert server.apply_effects(sine, server.SynthesisRequest(quality_level="low", **plain)) is sine
+    assert server.apply_effects(sine, server.SynthesisRequest(quality_level="high", **plain)) is not sine
+    assert len(server.resample_linear(sine, 24000, 44100)) == 44100
+    body = {"text": "Hello", "voice": VOICE, "sample_rate": 48000}
+    low = client.post("/synthesize", json={**body, "quality_level": "low"}).json()
+    assert low["duration_seconds"] == client.post("/synthesize", json=body).json()["duration_seconds"]
+
+def test_id3_comment_names_the_voice_used(monkeypatch):
+    if "mp3" not in server.supported_formats():
+        pytest.skip("libsndfile without MP3")
+    monkeypatch.setattr(server, "default_voices", {"es": VOICE})
+    monkeypatch.setattr(server.settings, "voice", "zz_elsewhere")
+    req = server.SynthesisRequest(text="hola", language="es", format="mp3", id3_tags=True)
+    data = server.encode_for_request(server.render(req), req)
+    assert f"voice={VOICE} ".encode("utf-16-le") in data
+
+@pytest.mark.parametrize("speed", [0, -1.0, 10.0])
+def test_speed_outside_the_range_is_rejected(client, speed):
+    response = client.post("/synthesize", json={"text": "Hello", "voice": VOICE, "speed": speed})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_REQUEST"
+    assert response.json()["detail"][0]["loc"] == ["body", "speed"]
+
+def test_play_live_synthesizes_in_the_request_language(client, monkeypatch):
+    played = []
+    monkeypatch.setattr(server.sd, "play", lambda audio, samplerate: played.append((len(audio), samplerate)))
+    server.pipelines.pop("e", None)
+    response = client.post("/play_live", json={"text": "hola", "voice": VOICE, "language": "es"})
+    assert response.json() == {"status": "played"}
+    assert played == [(len("hola") * SAMPLES_PER_PHONEME, 24000)]
+    assert "e" in server.pipelines
+
+def test_g2p_runs_one_call_at_a_time():
+    pipeline = server.create_pipeline("a")
+    with server.g2p_lock:
+        worker = threading.Thread(target=pipeline.g2p, args=("hello",))
+        worker.start()
+        worker.join(0.2)
+        assert worker.is_alive()  # waiting for the lock
+    worker.join(5)
+    assert not worker.is_alive()
+
+def test_projection_of_a_single_voice(client):
+    assert client.get("/voices/projection").json() == [{"name": VOICE, "x": 0.0, "y": 0.0}]
+
+def test_voice_details_of_the_test_pack(client):
+    body = client.get(f"/voices/{VOICE}").json()
+    assert body["embedding_shape"] == [510, 1, 256] and body["well_formed"] is True
+    assert client.get("/voices/zz_nobody").json()["error_code"] == "VOICE_NOT_FOUND"
+
+def test_stream_rejects_unknown_language_up_front(client):
+    body = {"text": "Hello", "voice": VOICE, "language": "xx", "normalize": False}
+    response = client.post("/synthesize/stream", json=body)
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "UNSUPPORTED_LANGUAGE"
+
+def test_websocket_reports_unexpected_errors(client, monkeypatch):
+    request = json.dumps({"text": "Hello", "voice": VOICE})
+    with client.websocket_connect("/ws") as ws:
+        def broken(audio, bit_depth=16):
+            raise RuntimeError("encoder exploded")
+        monkeypatch.setattr(server, "to_pcm", broken)
+        ws.send_text(request)
+        assert ws.receive_json()["error_code"] == "SYNTHESIS_FAILED"
+        monkeypatch.undo()
+        ws.send_text(request)
+        assert ws.receive_bytes()
+        assert ws.receive_json() == {"event": "done"}
+
+def test_batch_items_fail_independently_even_unexpectedly(client, monkeypatch):
+    respond = server.synthesis_response
+
+    def flaky(rendered, req):
+        if req.text == "Boom":
+            raise RuntimeError("encoder exploded")
+        return respond(rendered, req)
+
+    monkeypatch.setattr(server, "synthesis_response", flaky)
+    items = [{"text": "Hello", "voice": VOICE}, {"text": "Boom", "voice": VOICE}]
+    ok, failed = client.post("/synthesize/batch", json={"items": items}).json()
+    assert ok["status_code"] == 200 and ok["result"]["audio_data"]
+    assert (failed["index"], failed["status_code"], failed["error_code"]) == (1, 500, "SYNTHESIS_FAILED")
+
+def test_batch_and_warmup_sizes_are_capped(client):
+    items = [{"text": "Hello", "voice": VOICE}] * (server.MAX_BATCH_ITEMS + 1)
+    assert client.post("/synthesize/batch", json={"items": items}).json()["error_code"] == "INVALID_REQUEST"
+    phrases = ["Hello"] * (server.MAX_BATCH_ITEMS + 1)
+    assert client.post("/warmup", json={"phrases": phrases}).json()["error_code"] == "INVALID_REQUEST"
+
+@pytest.mark.parametrize("gate", [{"gate_attack_ms": "Infinity"}, {"gate_release_ms": "NaN"},
+                                  {"noise_gate_db": "-Infinity"}, {"gate_attack_ms": 10 ** 6}])
+def test_gate_fields_reject_unbounded_numbers(client, gate):
+    response = post_raw_numbers(client, gate)
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_REQUEST"
+
+@pytest.mark.parametrize("auto", [{"auto_compressed_format": '"mulaw"'}, {"auto_format_threshold_s": "Infinity"}])
+def test_auto_format_settings_are_limited(client, auto):
+    response = post_raw_numbers(client, {"format": '"auto"', **auto})
+    assert response.status_code == 400
+    assert response.json()["error_code"] == "INVALID_REQUEST"